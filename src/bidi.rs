@@ -0,0 +1,187 @@
+use unicode_bidi::{BidiInfo, Level, ParagraphBidiInfo};
+
+/**
+ * The base direction of a paragraph, from the `dir` attribute
+ */
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Direction {
+    #[default]
+    Ltr,
+    Rtl,
+    /// Whichever direction the first strongly directional character has
+    Auto,
+}
+
+impl Direction {
+    pub fn parse(value: &str) -> Option<Direction> {
+        Some(match value.trim().to_ascii_lowercase().as_str() {
+            "ltr" => Direction::Ltr,
+            "rtl" => Direction::Rtl,
+            "auto" => Direction::Auto,
+            _ => return None,
+        })
+    }
+
+    fn level(self) -> Option<Level> {
+        match self {
+            Direction::Ltr => Some(Level::ltr()),
+            Direction::Rtl => Some(Level::rtl()),
+            Direction::Auto => None,
+        }
+    }
+}
+
+/**
+ * Text whose characters are drawn in a different order from the one they're read in
+ */
+pub struct Reordered {
+    /// The text in reading order
+    pub logical: String,
+    /// For each character from left to right, the index of the character in `logical` drawn there
+    /// and whether it runs right to left
+    pub order: Vec<(usize, bool)>,
+}
+
+impl Reordered {
+    /**
+     * The drawn text, left to right
+     */
+    pub fn visual(&self) -> String {
+        let chars: Vec<char> = self.logical.chars().collect();
+        self.order.iter().map(|(index, _)| chars[*index]).collect()
+    }
+
+    /**
+     * Where a position between characters of the logical text is drawn, as a position between
+     * the drawn characters. A position is drawn beside the character it comes before, or after it
+     * at the end, on whichever side that character is read from.
+     */
+    pub fn visual_offset(&self, logical: usize) -> usize {
+        let at_end = logical >= self.order.len();
+        let before = if at_end {
+            self.order.len().saturating_sub(1)
+        } else {
+            logical
+        };
+        let Some(visual) = self.order.iter().position(|(index, _)| *index == before) else {
+            return 0;
+        };
+        let rtl = self.order[visual].1;
+        if rtl == at_end {
+            visual
+        } else {
+            visual + 1
+        }
+    }
+
+    /**
+     * The logical position between characters for a position between drawn characters
+     */
+    pub fn logical_offset(&self, visual: usize) -> usize {
+        match self.order.get(visual) {
+            Some((index, rtl)) => index + *rtl as usize,
+            None => match self.order.last() {
+                Some((index, rtl)) => index + !*rtl as usize,
+                None => 0,
+            },
+        }
+    }
+}
+
+/**
+ * The order a word's characters are drawn in, left to right, as the index of each character and
+ * whether it runs right to left. None if they're drawn in the order they're read in.
+ */
+pub fn visual_order(text: &str, direction: Direction) -> Option<Vec<(usize, bool)>> {
+    // ASCII has no right-to-left characters, which is worth checking for since it's so common
+    if direction == Direction::Ltr && text.is_ascii() {
+        return None;
+    }
+    let info = ParagraphBidiInfo::new(text, direction.level());
+    if !info.has_rtl() {
+        return None;
+    }
+    let starts: Vec<usize> = text.char_indices().map(|(start, _)| start).collect();
+    let (levels, runs) = info.visual_runs(0..text.len());
+    let mut order = Vec::with_capacity(starts.len());
+    for run in runs {
+        let rtl = levels[run.start].is_rtl();
+        let chars = text[run.clone()].char_indices().map(|(offset, _)| {
+            let index = starts.binary_search(&(run.start + offset)).unwrap_or(0);
+            (index, rtl)
+        });
+        if rtl {
+            order.extend(chars.rev());
+        } else {
+            order.extend(chars);
+        }
+    }
+    Some(order)
+}
+
+/**
+ * Work out the order to draw a line's items in, given each item's text in logical order. Returns
+ * whether the line runs right to left, and the logical index of each item from left to right, or
+ * None if they're already in order.
+ */
+pub fn line_order(texts: &[&str], direction: Direction) -> (bool, Option<Vec<usize>>) {
+    if direction == Direction::Ltr && texts.iter().all(|text| text.is_ascii()) {
+        return (false, None);
+    }
+    let mut line = String::new();
+    let mut starts = Vec::with_capacity(texts.len());
+    for text in texts {
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        starts.push(line.len());
+        line.push_str(text);
+    }
+    let info = ParagraphBidiInfo::new(&line, direction.level());
+    let rtl = info.paragraph_level.is_rtl();
+    if !info.has_rtl() {
+        return (rtl, None);
+    }
+    // Each item moves as a whole, at the level of its first character
+    let levels: Vec<Level> = starts
+        .iter()
+        .map(|start| {
+            info.levels
+                .get(*start)
+                .copied()
+                .unwrap_or(info.paragraph_level)
+        })
+        .collect();
+    (rtl, Some(BidiInfo::reorder_visual(&levels)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reordered(text: &str, direction: Direction) -> Reordered {
+        Reordered {
+            logical: text.to_string(),
+            order: visual_order(text, direction).unwrap(),
+        }
+    }
+
+    #[test]
+    fn reverses_right_to_left_words() {
+        let word = reordered("שלום", Direction::Ltr);
+        assert_eq!(word.visual(), "םולש");
+        // Reading starts at the right
+        assert_eq!(word.visual_offset(0), 4);
+        assert_eq!(word.visual_offset(4), 0);
+        assert_eq!(word.logical_offset(3), 1);
+    }
+
+    #[test]
+    fn keeps_left_to_right_runs_in_order() {
+        let word = reordered("אב12", Direction::Rtl);
+        assert_eq!(word.visual(), "12בא");
+        assert_eq!(word.visual_offset(2), 0);
+        assert_eq!(word.logical_offset(1), 3);
+        assert!(visual_order("hello", Direction::Ltr).is_none());
+    }
+}
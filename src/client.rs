@@ -1,27 +1,215 @@
 use std::{
     collections::{HashMap, HashSet},
+    fs::File,
     io::{self, BufRead, BufReader, Read, Write},
-    net::TcpStream,
+    net::{TcpStream, ToSocketAddrs},
     str::Utf8Error,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
+use base64::{prelude::BASE64_STANDARD, Engine};
 use rustls::{pki_types::InvalidDnsNameError, ClientConfig, ClientConnection};
 use thiserror::Error;
 
 use crate::url::{Scheme, Url, UrlError};
 
+/**
+ * Makes requests, keeping connections open for reuse. Requests take `&self`, so one client can be
+ * shared between threads, each request taking a connection out of the pool while it uses it.
+ */
 pub struct Client {
     config: Arc<ClientConfig>,
-    static_hosts: HashSet<&'static str>,
-    open_http_streams: HashMap<String, TcpStream>,
-    open_https_streams: HashMap<String, (ClientConnection, TcpStream)>,
+    timeouts: Timeouts,
+    max_body_size: usize,
+    static_hosts: Mutex<HashSet<&'static str>>,
+    open_http_streams: Mutex<HashMap<String, TcpStream>>,
+    open_https_streams: Mutex<HashMap<String, (ClientConnection, TcpStream)>>,
 }
 
 const MAX_REDIRECTS: u16 = 128;
 
+/**
+ * The largest body a response may have unless `set_max_body_size` says otherwise
+ */
+pub const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+
+/**
+ * The most the status line and headers of a response may add up to, and likewise its trailers
+ */
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/**
+ * The longest line giving a chunk's size, including any extensions
+ */
+const MAX_CHUNK_LINE: usize = 4 * 1024;
+
+/**
+ * How much of a body is read at a time, and so the most a streaming callback gets at once
+ */
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+/**
+ * Called with each piece of a streamed response body as it arrives
+ */
+pub type OnChunk<'a> = dyn FnMut(&[u8]) -> io::Result<()> + 'a;
+
+/**
+ * How often a blocked read wakes up to check whether its request has been cancelled
+ */
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/**
+ * How long a request may wait before giving up with `RequestError::Timeout`. `None` waits forever.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct Timeouts {
+    /// Opening a connection to each of the server's addresses
+    pub connect: Option<Duration>,
+    /// Each wait for the server to send or accept more data
+    pub read: Option<Duration>,
+    /// The whole request, including following redirects
+    pub total: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            connect: Some(Duration::from_secs(10)),
+            read: Some(Duration::from_secs(30)),
+            total: Some(Duration::from_secs(120)),
+        }
+    }
+}
+
+/**
+ * Lets another thread cancel a request, which then fails with `RequestError::Cancelled`. Clones
+ * share the same flag.
+ */
+#[derive(Clone, Default, Debug)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/**
+ * The limits one call to `request` runs under, shared by every redirect it follows
+ */
+#[derive(Copy, Clone)]
+struct Attempt<'a> {
+    read: Option<Duration>,
+    deadline: Option<Instant>,
+    cancel: &'a CancelHandle,
+}
+
+impl Attempt<'_> {
+    /**
+     * How long the next wait may last, given when it would otherwise time out. Fails if the
+     * request has been cancelled or has already run out of time.
+     */
+    fn wait(&self, limit: Option<Duration>) -> io::Result<Option<Duration>> {
+        if self.cancel.is_cancelled() {
+            return Err(io::Error::other(Cancelled));
+        }
+        let total = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let wait = match (limit, total) {
+            (Some(limit), Some(total)) => Some(limit.min(total)),
+            (limit, total) => limit.or(total),
+        };
+        if wait.is_some_and(|wait| wait.is_zero()) {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        Ok(wait)
+    }
+}
+
+/**
+ * The error a cancelled request's reads fail with, so it can be told apart from other I/O errors
+ */
+#[derive(Debug, Error)]
+#[error("request cancelled")]
+struct Cancelled;
+
+/**
+ * A connection which enforces an attempt's timeouts on every read and write. Reads wake up
+ * regularly to notice cancellation.
+ */
+struct TimedStream<'a> {
+    stream: &'a mut TcpStream,
+    attempt: Attempt<'a>,
+}
+
+impl Read for TimedStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let started = Instant::now();
+        loop {
+            let idle = self
+                .attempt
+                .read
+                .map(|read| read.saturating_sub(started.elapsed()));
+            let wait = self.attempt.wait(idle)?;
+            let wait = wait.map_or(CANCEL_POLL_INTERVAL, |wait| wait.min(CANCEL_POLL_INTERVAL));
+            self.stream.set_read_timeout(Some(wait))?;
+            match self.stream.read(buf) {
+                Err(err) if is_timeout(&err) => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Write for TimedStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let wait = self.attempt.wait(self.attempt.read)?;
+        self.stream.set_write_timeout(wait)?;
+        self.stream.write(buf).map_err(|err| {
+            if is_timeout(&err) {
+                io::ErrorKind::TimedOut.into()
+            } else {
+                err
+            }
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/**
+ * Socket timeouts show up as `WouldBlock` on some platforms and `TimedOut` on others
+ */
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
 impl Client {
     pub fn new() -> Client {
+        Client::with_timeouts(Timeouts::default())
+    }
+
+    pub fn with_timeouts(timeouts: Timeouts) -> Client {
         let root_store =
             rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
         let config = Arc::new(
@@ -32,70 +220,198 @@ impl Client {
 
         Client {
             config,
-            static_hosts: HashSet::new(),
-            open_http_streams: HashMap::new(),
-            open_https_streams: HashMap::new(),
+            timeouts,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            static_hosts: Mutex::default(),
+            open_http_streams: Mutex::default(),
+            open_https_streams: Mutex::default(),
         }
     }
 
-    pub fn request(&mut self, url: &Url) -> Result<Response, RequestError> {
-        self.request_internal(url, MAX_REDIRECTS)
+    /**
+     * Refuse responses with bodies bigger than this many bytes, with `RequestError::BodyTooLarge`
+     */
+    pub fn set_max_body_size(&mut self, max_body_size: usize) {
+        self.max_body_size = max_body_size;
+    }
+
+    pub fn request(&self, url: &Url) -> Result<Response, RequestError> {
+        self.request_cancellable(url, &CancelHandle::default())
+    }
+
+    /**
+     * Make a request that gives up as soon as `cancel` is triggered from another thread
+     */
+    pub fn request_cancellable(
+        &self,
+        url: &Url,
+        cancel: &CancelHandle,
+    ) -> Result<Response, RequestError> {
+        self.request_internal(url, MAX_REDIRECTS, self.attempt(cancel), None)
+    }
+
+    /**
+     * Make a request, handing the body to `on_chunk` piece by piece as it arrives instead of
+     * keeping it. The response's body is `Body::Streamed`. Redirects are followed without their
+     * bodies reaching `on_chunk`. An error from `on_chunk` abandons the request.
+     */
+    pub fn request_streaming(
+        &self,
+        url: &Url,
+        cancel: &CancelHandle,
+        on_chunk: &mut OnChunk,
+    ) -> Result<Response, RequestError> {
+        self.request_internal(url, MAX_REDIRECTS, self.attempt(cancel), Some(on_chunk))
+    }
+
+    fn attempt<'a>(&self, cancel: &'a CancelHandle) -> Attempt<'a> {
+        Attempt {
+            read: self.timeouts.read,
+            deadline: self.timeouts.total.map(|total| Instant::now() + total),
+            cancel,
+        }
     }
 
     fn request_internal(
-        &mut self,
+        &self,
         url: &Url,
         remaining_redirects: u16,
+        attempt: Attempt,
+        mut on_chunk: Option<&mut OnChunk>,
     ) -> Result<Response, RequestError> {
         eprintln!("Making a GET request to {url}");
-        let host = url.host();
+        // Connections are kept per origin, since one host can serve different sites on each port
+        let origin = url.origin();
 
         match url.scheme() {
             Scheme::Http => {
-                if !self.open_http_streams.contains_key(host) {
-                    let stream = TcpStream::connect((host, url.port().unwrap_or(80)))?;
-                    self.open_http_streams.insert(host.to_string(), stream);
-                }
-
-                let mut stream = self.open_http_streams.get_mut(host).unwrap();
-                send_get(url, &mut stream)?;
-
-                let resp = recv_response(BufReader::new(stream))?;
-                self.handle_redirect(url, resp, remaining_redirects)
+                let resp = self.http_get(url, &origin, attempt, on_chunk.as_deref_mut())?;
+                self.handle_redirect(url, resp, remaining_redirects, attempt, on_chunk)
             }
             Scheme::Https => {
-                if !self.open_https_streams.contains_key(host) {
-                    let static_host = self.static_host(host);
-                    let tls = rustls::ClientConnection::new(
-                        self.config.clone(),
-                        static_host.try_into()?,
-                    )?;
-                    let tcp = TcpStream::connect((host, url.port().unwrap_or(443)))?;
-                    self.open_https_streams.insert(host.to_string(), (tls, tcp));
-                }
-                let (tls, stream) = self.open_https_streams.get_mut(host).unwrap();
-                let mut stream = rustls::Stream::new(tls, stream);
-                send_get(url, &mut stream)?;
-                let resp = recv_response(BufReader::new(stream))?;
-                self.handle_redirect(url, resp, remaining_redirects)
+                let resp = self.https_get(url, &origin, attempt, on_chunk.as_deref_mut())?;
+                self.handle_redirect(url, resp, remaining_redirects, attempt, on_chunk)
             }
             Scheme::File => {
-                return Ok(Response {
+                let mut body = BodySink::new(self.max_body_size, on_chunk);
+                copy_body(&mut File::open(url.path())?, None, &mut body)?;
+                Ok(Response {
                     status_code: 200,
                     headers: HashMap::new(),
-                    body: Body::Text(std::fs::read_to_string(url.path())?),
-                });
+                    body: body.finish(),
+                })
+            }
+            Scheme::Data => {
+                let response = data_response(url.path())?;
+                let Body::Bytes(data) = &response.body else {
+                    unreachable!("data: URLs are decoded into memory");
+                };
+                let mut body = BodySink::new(self.max_body_size, on_chunk);
+                body.push(data)?;
+                Ok(Response {
+                    body: body.finish(),
+                    ..response
+                })
+            }
+        }
+    }
+
+    // Each request takes its connection out of the pool and only puts it back once the response
+    // has been read in full. A failed request can leave part of its response unread, which the
+    // next request on the same connection would take for its own, so its connection is dropped.
+
+    fn http_get(
+        &self,
+        url: &Url,
+        origin: &str,
+        attempt: Attempt,
+        on_chunk: Option<&mut OnChunk>,
+    ) -> Result<Response, RequestError> {
+        let pooled = self.open_http_streams.lock().unwrap().remove(origin);
+        let mut stream = match pooled {
+            Some(stream) => stream,
+            None => self.connect(url.host(), url.port().unwrap_or(80), attempt)?,
+        };
+        let mut timed = TimedStream {
+            stream: &mut stream,
+            attempt,
+        };
+        send_get(url, &mut timed)?;
+        let response = recv_response(BufReader::new(timed), self.max_body_size, on_chunk)?;
+        self.open_http_streams
+            .lock()
+            .unwrap()
+            .insert(origin.to_string(), stream);
+        Ok(response)
+    }
+
+    fn https_get(
+        &self,
+        url: &Url,
+        origin: &str,
+        attempt: Attempt,
+        on_chunk: Option<&mut OnChunk>,
+    ) -> Result<Response, RequestError> {
+        let pooled = self.open_https_streams.lock().unwrap().remove(origin);
+        let (mut tls, mut tcp) = match pooled {
+            Some(connection) => connection,
+            None => {
+                let static_host = self.static_host(url.host());
+                let tls =
+                    rustls::ClientConnection::new(self.config.clone(), static_host.try_into()?)?;
+                let tcp = self.connect(url.host(), url.port().unwrap_or(443), attempt)?;
+                (tls, tcp)
+            }
+        };
+        let mut timed = TimedStream {
+            stream: &mut tcp,
+            attempt,
+        };
+        let mut stream = rustls::Stream::new(&mut tls, &mut timed);
+        send_get(url, &mut stream)?;
+        let response = recv_response(BufReader::new(stream), self.max_body_size, on_chunk)?;
+        self.open_https_streams
+            .lock()
+            .unwrap()
+            .insert(origin.to_string(), (tls, tcp));
+        Ok(response)
+    }
+
+    /**
+     * Connect to the first of the host's addresses that answers in time. Looking the host up
+     * isn't covered by the timeouts, since the system resolver can't be interrupted.
+     */
+    fn connect(&self, host: &str, port: u16, attempt: Attempt) -> Result<TcpStream, RequestError> {
+        let mut last_error = None;
+        for address in (host, port).to_socket_addrs()? {
+            let result = match attempt.wait(self.timeouts.connect)? {
+                Some(timeout) => TcpStream::connect_timeout(&address, timeout),
+                None => TcpStream::connect(address),
+            };
+            match result {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = Some(err),
             }
         }
+        Err(last_error
+            .unwrap_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no addresses found for {host}"),
+                )
+            })
+            .into())
     }
 
     fn handle_redirect(
-        &mut self,
+        &self,
         url: &Url,
         mut response: Response,
         remaining_redirects: u16,
+        attempt: Attempt,
+        on_chunk: Option<&mut OnChunk>,
     ) -> Result<Response, RequestError> {
-        if response.status_code >= 300 && response.status_code < 400 {
+        if is_redirect(response.status_code) {
             if remaining_redirects == 0 {
                 return Err(RequestError::MaximumRedirects);
             }
@@ -108,7 +424,7 @@ impl Client {
                         .map_err(|e| RequestError::BadRedirectUrl(location, e))?
                 };
                 eprintln!("Redirecting from {url} to {redirect}");
-                return self.request_internal(&redirect, remaining_redirects - 1);
+                self.request_internal(&redirect, remaining_redirects - 1, attempt, on_chunk)
             } else {
                 Err(RequestError::NoRedirectFound)
             }
@@ -120,24 +436,87 @@ impl Client {
     /**
      * Pull the static str from cache or create and cache a new one
      */
-    fn static_host(&mut self, host: &str) -> &'static str {
-        match self.static_hosts.get(host) {
-            Some(static_host) => *static_host,
+    fn static_host(&self, host: &str) -> &'static str {
+        let mut static_hosts = self.static_hosts.lock().unwrap();
+        match static_hosts.get(host) {
+            Some(static_host) => static_host,
             None => {
                 let static_host = host.to_string().leak() as &'static str;
-                self.static_hosts.insert(static_host);
+                static_hosts.insert(static_host);
                 static_host
             }
         }
     }
 }
 
+/**
+ * Decode the contents of a data: URL, which look like `image/png;base64,iVBORw0...` or
+ * `text/html,<p>hello</p>`
+ */
+fn data_response(data: &str) -> Result<Response, RequestError> {
+    let (media_type, payload) = data.split_once(',').ok_or(RequestError::BadDataUrl)?;
+    let (media_type, base64) = match media_type.strip_suffix(";base64") {
+        Some(media_type) => (media_type, true),
+        None => (media_type, false),
+    };
+
+    let body = if base64 {
+        let payload: String = percent_decode(payload)
+            .into_iter()
+            .map(char::from)
+            .filter(|c| !c.is_ascii_whitespace())
+            .collect();
+        BASE64_STANDARD
+            .decode(payload)
+            .map_err(|_| RequestError::BadDataUrl)?
+    } else {
+        percent_decode(payload)
+    };
+
+    let mut headers = HashMap::new();
+    if !media_type.is_empty() {
+        headers.insert("Content-Type".to_string(), media_type.to_string());
+    }
+    Ok(Response {
+        status_code: 200,
+        headers,
+        body: Body::Bytes(body),
+    })
+}
+
+fn percent_decode(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
 fn send_get(url: &Url, stream: &mut dyn Write) -> Result<(), RequestError> {
-    write!(stream, "GET {} HTTP/1.1\r\n", url.path())?;
-    header_line(stream, "host", url.host())?;
-    //header_line(stream, "Connection", "close")?;
-    header_line(stream, "User-Agent", "shelves")?;
-    write!(stream, "\r\n")?;
+    // Sent in one write, since small writes on a kept-alive connection wait on delayed ACKs
+    let mut request = Vec::new();
+    write!(request, "GET {} HTTP/1.1\r\n", url.path())?;
+    header_line(&mut request, "host", url.host())?;
+    //header_line(&mut request, "Connection", "close")?;
+    header_line(&mut request, "User-Agent", "shelves")?;
+    write!(request, "\r\n")?;
+    stream.write_all(&request)?;
     Ok(())
 }
 
@@ -146,9 +525,14 @@ fn header_line(stream: &mut dyn Write, key: &str, value: &str) -> Result<(), Req
     Ok(())
 }
 
-fn recv_response<T: Read>(mut lines: BufReader<T>) -> Result<Response, RequestError> {
-    let mut statusline = String::new();
-    lines.read_line(&mut statusline)?;
+fn recv_response<T: Read>(
+    mut lines: BufReader<T>,
+    max_body_size: usize,
+    on_chunk: Option<&mut OnChunk>,
+) -> Result<Response, RequestError> {
+    let mut head_remaining = MAX_HEAD_SIZE;
+    let statusline = read_line(&mut lines, head_remaining)?;
+    head_remaining -= statusline.len();
 
     let (_http_version, statusline) = statusline.split_once(" ").ok_or(RequestError::BadHTTP)?;
     let (status_code, _explanation) = statusline.split_once(" ").ok_or(RequestError::BadHTTP)?;
@@ -158,8 +542,8 @@ fn recv_response<T: Read>(mut lines: BufReader<T>) -> Result<Response, RequestEr
 
     let mut headers = HashMap::new();
     loop {
-        let mut header_line = String::new();
-        lines.read_line(&mut header_line)?;
+        let header_line = read_line(&mut lines, head_remaining)?;
+        head_remaining -= header_line.len();
         if header_line.trim().is_empty() {
             break;
         }
@@ -169,29 +553,163 @@ fn recv_response<T: Read>(mut lines: BufReader<T>) -> Result<Response, RequestEr
             .ok_or(RequestError::BadHTTP)?;
         headers.insert(header.to_string(), value.to_string());
     }
-    assert!(!headers.contains_key("transfer-encoding"));
-    assert!(!headers.contains_key("content-encoding"));
-
-    let content_length: u32 = headers
-        .get("Content-Length")
-        .ok_or(RequestError::MissingContentLength)?
-        .parse()
-        .map_err(|_| RequestError::BadHTTP)?;
-    let mut body = vec![0; content_length as usize];
-    let existing_buffer = lines.buffer();
-    let buffer_len = existing_buffer.len();
-    body[0..buffer_len].copy_from_slice(existing_buffer);
-
-    let mut inner = lines.into_inner();
-    inner.read_exact(&mut body[buffer_len..])?;
+    // Requests don't ask for compression, so compressed bodies can only be garbage to us
+    if let Some(encoding) = headers
+        .get("Content-Encoding")
+        .filter(|encoding| !encoding.trim().eq_ignore_ascii_case("identity"))
+    {
+        return Err(RequestError::UnsupportedEncoding(encoding.clone()));
+    }
+    let chunked = match headers.get("Transfer-Encoding") {
+        None => false,
+        Some(encoding) if encoding.trim().eq_ignore_ascii_case("chunked") => true,
+        Some(encoding) => return Err(RequestError::UnsupportedEncoding(encoding.clone())),
+    };
+    // A redirect's body is only a note for people, so it's never what the caller is streaming
+    let on_chunk = on_chunk.filter(|_| !is_redirect(status_code));
+    let mut body = BodySink::new(max_body_size, on_chunk);
+    if chunked {
+        read_chunked(&mut lines, &mut body)?;
+    } else {
+        let content_length: usize = headers
+            .get("Content-Length")
+            .ok_or(RequestError::MissingContentLength)?
+            .trim()
+            .parse()
+            .map_err(|_| RequestError::BadHTTP)?;
+        // Checked up front so a huge length fails fast, rather than after downloading the limit
+        if content_length > max_body_size {
+            return Err(RequestError::BodyTooLarge(max_body_size));
+        }
+        copy_body(&mut lines, Some(content_length), &mut body)?;
+    }
 
     Ok(Response {
         status_code,
         headers,
-        body: Body::Bytes(body),
+        body: body.finish(),
     })
 }
 
+fn is_redirect(status_code: u16) -> bool {
+    (300..400).contains(&status_code)
+}
+
+/**
+ * Where a response body goes as it's read: into memory, or to a streaming callback
+ */
+struct BodySink<'a, 'b> {
+    max_size: usize,
+    length: usize,
+    collected: Vec<u8>,
+    on_chunk: Option<&'a mut OnChunk<'b>>,
+}
+
+impl<'a, 'b> BodySink<'a, 'b> {
+    fn new(max_size: usize, on_chunk: Option<&'a mut OnChunk<'b>>) -> BodySink<'a, 'b> {
+        BodySink {
+            max_size,
+            length: 0,
+            collected: Vec::new(),
+            on_chunk,
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) -> Result<(), RequestError> {
+        self.length += chunk.len();
+        if self.length > self.max_size {
+            return Err(RequestError::BodyTooLarge(self.max_size));
+        }
+        match &mut self.on_chunk {
+            Some(on_chunk) => on_chunk(chunk)?,
+            None => self.collected.extend_from_slice(chunk),
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Body {
+        match self.on_chunk {
+            Some(_) => Body::Streamed(self.length),
+            None => Body::Bytes(self.collected),
+        }
+    }
+}
+
+/**
+ * Pass `length` bytes of body to the sink, or everything up to the end of the stream if there's
+ * no length
+ */
+fn copy_body(
+    reader: &mut dyn Read,
+    length: Option<usize>,
+    body: &mut BodySink<'_, '_>,
+) -> Result<(), RequestError> {
+    let mut buffer = [0; READ_CHUNK_SIZE];
+    let mut remaining = length;
+    while remaining != Some(0) {
+        let wanted = remaining.map_or(buffer.len(), |remaining| remaining.min(buffer.len()));
+        let read = match reader.read(&mut buffer[..wanted]) {
+            Ok(0) if remaining.is_some() => {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+            }
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        body.push(&buffer[..read])?;
+        remaining = remaining.map(|remaining| remaining - read);
+    }
+    Ok(())
+}
+
+/**
+ * Read a body sent with `Transfer-Encoding: chunked`: chunks prefixed with their size in hex,
+ * ending with an empty chunk
+ */
+fn read_chunked<T: Read>(
+    lines: &mut BufReader<T>,
+    body: &mut BodySink<'_, '_>,
+) -> Result<(), RequestError> {
+    loop {
+        let size_line = read_line(lines, MAX_CHUNK_LINE)?;
+        // Chunk extensions may follow the size after a semicolon, and nothing uses them
+        let size = size_line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| RequestError::BadHTTP)?;
+        if size == 0 {
+            break;
+        }
+        copy_body(lines, Some(size), body)?;
+        let chunk_end = read_line(lines, MAX_CHUNK_LINE)?;
+        if !chunk_end.trim().is_empty() {
+            return Err(RequestError::BadHTTP);
+        }
+    }
+    // Skip any trailer fields, up to the blank line ending the body
+    let mut trailers_remaining = MAX_HEAD_SIZE;
+    loop {
+        let trailer = read_line(lines, trailers_remaining)?;
+        trailers_remaining -= trailer.len();
+        if trailer.trim().is_empty() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Read a line of at most `limit` bytes. A line still going past that is malformed, so a server
+ * can't make us buffer it without end. The line is empty at the end of the stream.
+ */
+fn read_line<T: Read>(lines: &mut BufReader<T>, limit: usize) -> Result<String, RequestError> {
+    let mut line = String::new();
+    let read = lines.by_ref().take(limit as u64).read_line(&mut line)?;
+    if read == limit && !line.ends_with('\n') {
+        return Err(RequestError::BadHTTP);
+    }
+    Ok(line)
+}
+
 pub struct Response {
     pub status_code: u16,
     pub headers: HashMap<String, String>,
@@ -200,22 +718,39 @@ pub struct Response {
 
 pub enum Body {
     Bytes(Vec<u8>),
-    Text(String),
+    /// The body went to the callback given to `Client::request_streaming`, and was this many
+    /// bytes long
+    Streamed(usize),
 }
 
 impl Body {
-    pub fn as_str(&self) -> Result<&str, Utf8Error> {
+    /**
+     * The body's contents, which aren't around if it was streamed
+     */
+    pub fn as_bytes(&self) -> Result<&[u8], BodyError> {
         match self {
-            Body::Bytes(bytes) => std::str::from_utf8(bytes),
-            Body::Text(text) => Ok(text),
+            Body::Bytes(bytes) => Ok(bytes),
+            Body::Streamed(_) => Err(BodyError::Streamed),
         }
     }
+
+    pub fn as_str(&self) -> Result<&str, BodyError> {
+        Ok(std::str::from_utf8(self.as_bytes()?)?)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BodyError {
+    #[error("the body was streamed rather than kept")]
+    Streamed,
+    #[error("body isn't valid UTF-8: {0}")]
+    NotUtf8(#[from] Utf8Error),
 }
 
 #[derive(Debug, Error)]
 pub enum RequestError {
     #[error("io error: {0}")]
-    IOError(#[from] io::Error),
+    IOError(#[source] io::Error),
     #[error("tls error: {0}")]
     TlsError(#[from] rustls::Error),
     #[error("dns error: {0}")]
@@ -232,4 +767,212 @@ pub enum RequestError {
     NoRedirectFound,
     #[error("missing Content-Length")]
     MissingContentLength,
+    #[error("unsupported encoding: {0}")]
+    UnsupportedEncoding(String),
+    #[error("malformed data URL")]
+    BadDataUrl,
+    #[error("request timed out")]
+    Timeout,
+    #[error("request cancelled")]
+    Cancelled,
+    #[error("response body is bigger than the {0} byte limit")]
+    BodyTooLarge(usize),
+}
+
+impl From<io::Error> for RequestError {
+    fn from(err: io::Error) -> RequestError {
+        if err.get_ref().is_some_and(|inner| inner.is::<Cancelled>()) {
+            RequestError::Cancelled
+        } else if err.kind() == io::ErrorKind::TimedOut {
+            RequestError::Timeout
+        } else {
+            RequestError::IOError(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(response: &str) -> Result<Response, RequestError> {
+        recv_response(BufReader::new(response.as_bytes()), 1024, None)
+    }
+
+    #[test]
+    fn reads_status_headers_and_body() {
+        let response =
+            parse("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 5\r\n\r\nhello")
+                .unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.headers["Content-Type"], "text/html");
+        assert_eq!(response.body.as_bytes().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn reads_only_content_length_bytes() {
+        let response = parse("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhello").unwrap();
+        assert_eq!(response.body.as_bytes().unwrap(), b"he");
+    }
+
+    #[test]
+    fn reads_bodies_bigger_than_the_buffer() {
+        let body = "x".repeat(100);
+        let raw = format!("HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n{body}");
+        let response =
+            recv_response(BufReader::with_capacity(8, raw.as_bytes()), 1024, None).unwrap();
+        assert_eq!(response.body.as_bytes().unwrap(), body.as_bytes());
+    }
+
+    #[test]
+    fn status_line_without_a_reason_phrase_is_malformed() {
+        assert!(matches!(
+            parse("HTTP/1.1 200\r\n\r\n"),
+            Err(RequestError::BadHTTP)
+        ));
+    }
+
+    #[test]
+    fn non_numeric_status_codes_are_rejected() {
+        assert!(matches!(
+            parse("HTTP/1.1 OK 200\r\nContent-Length: 0\r\n\r\n"),
+            Err(RequestError::InvalidStatusCode(code)) if code == "OK"
+        ));
+    }
+
+    #[test]
+    fn headers_need_a_colon() {
+        assert!(matches!(
+            parse("HTTP/1.1 200 OK\r\nContent-Length 0\r\n\r\n"),
+            Err(RequestError::BadHTTP)
+        ));
+    }
+
+    #[test]
+    fn content_length_is_required() {
+        assert!(matches!(
+            parse("HTTP/1.1 200 OK\r\n\r\nhello"),
+            Err(RequestError::MissingContentLength)
+        ));
+    }
+
+    #[test]
+    fn truncated_bodies_are_an_error() {
+        assert!(matches!(
+            parse("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello"),
+            Err(RequestError::IOError(_))
+        ));
+    }
+
+    #[test]
+    fn compressed_bodies_are_unsupported() {
+        assert!(matches!(
+            parse("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: 0\r\n\r\n"),
+            Err(RequestError::UnsupportedEncoding(encoding)) if encoding == "gzip"
+        ));
+        assert!(matches!(
+            parse("HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n"),
+            Err(RequestError::UnsupportedEncoding(_))
+        ));
+    }
+
+    #[test]
+    fn empty_input_is_malformed() {
+        assert!(matches!(parse(""), Err(RequestError::BadHTTP)));
+    }
+
+    #[test]
+    fn joins_chunks() {
+        let response = parse(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n7;name=value\r\n, world\r\n0\r\nExpires: never\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.body.as_bytes().unwrap(), b"hello, world");
+    }
+
+    #[test]
+    fn chunk_sizes_must_be_hex() {
+        assert!(matches!(
+            parse("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n"),
+            Err(RequestError::BadHTTP)
+        ));
+    }
+
+    #[test]
+    fn decodes_data_urls() {
+        let response = data_response("text/html,%3Cp%3Ehi").unwrap();
+        assert_eq!(response.headers["Content-Type"], "text/html");
+        assert_eq!(response.body.as_bytes().unwrap(), b"<p>hi");
+
+        let response = data_response("image/png;base64,aGVs bG8=").unwrap();
+        assert_eq!(response.body.as_bytes().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn rejects_content_lengths_over_the_limit() {
+        // The length alone is enough to refuse, without waiting for a body that never comes
+        assert!(matches!(
+            parse("HTTP/1.1 200 OK\r\nContent-Length: 4294967296\r\n\r\n"),
+            Err(RequestError::BodyTooLarge(1024))
+        ));
+    }
+
+    #[test]
+    fn rejects_chunked_bodies_over_the_limit() {
+        let chunk = format!("200\r\n{}\r\n", "x".repeat(0x200));
+        let raw = format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{}0\r\n\r\n",
+            chunk.repeat(3)
+        );
+        assert!(matches!(parse(&raw), Err(RequestError::BodyTooLarge(1024))));
+    }
+
+    #[test]
+    fn endless_lines_are_malformed() {
+        let status = format!("HTTP/1.1 200 {}", "OK".repeat(MAX_HEAD_SIZE));
+        assert!(matches!(parse(&status), Err(RequestError::BadHTTP)));
+
+        let headers = "X-Padding: yes\r\n".repeat(MAX_HEAD_SIZE / 16);
+        let raw = format!("HTTP/1.1 200 OK\r\n{headers}Content-Length: 0\r\n\r\n");
+        assert!(matches!(parse(&raw), Err(RequestError::BadHTTP)));
+
+        let size = "0".repeat(MAX_CHUNK_LINE);
+        let raw = format!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{size}");
+        assert!(matches!(parse(&raw), Err(RequestError::BadHTTP)));
+    }
+
+    #[test]
+    fn streams_bodies_in_pieces() {
+        let body = "x".repeat(READ_CHUNK_SIZE + 10);
+        let raw = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mut pieces = Vec::new();
+        let response = recv_response(
+            BufReader::new(raw.as_bytes()),
+            DEFAULT_MAX_BODY_SIZE,
+            Some(&mut |chunk: &[u8]| {
+                pieces.push(chunk.len());
+                Ok(())
+            }),
+        )
+        .unwrap();
+        assert!(matches!(response.body, Body::Streamed(length) if length == body.len()));
+        assert!(matches!(response.body.as_str(), Err(BodyError::Streamed)));
+        assert!(pieces.len() > 1);
+        assert_eq!(pieces.iter().sum::<usize>(), body.len());
+    }
+
+    #[test]
+    fn collects_redirect_bodies_instead_of_streaming_them() {
+        let response = recv_response(
+            BufReader::new(&b"HTTP/1.1 302 Found\r\nContent-Length: 5\r\n\r\nmoved"[..]),
+            DEFAULT_MAX_BODY_SIZE,
+            Some(&mut |_: &[u8]| panic!("redirect bodies aren't the page")),
+        )
+        .unwrap();
+        assert_eq!(response.body.as_bytes().unwrap(), b"moved");
+    }
 }
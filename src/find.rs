@@ -0,0 +1,127 @@
+use shelves::layout::DisplayListItem;
+
+use crate::selection::{Selection, TextPosition};
+
+/**
+ * The state of the find-in-page bar for one tab
+ */
+pub struct Find {
+    pub query: String,
+    current: usize,
+    matches: Vec<Selection>,
+    /// The query and layout generation the matches were found for
+    searched: Option<(String, u64)>,
+}
+
+impl Find {
+    pub fn new() -> Find {
+        Find {
+            query: String::new(),
+            current: 0,
+            matches: Vec::new(),
+            searched: None,
+        }
+    }
+
+    /**
+     * Search again if the query or the page layout changed since the last search
+     */
+    pub fn update(&mut self, display_list: &[DisplayListItem], generation: u64) {
+        let up_to_date = self
+            .searched
+            .as_ref()
+            .is_some_and(|(query, searched)| *query == self.query && *searched == generation);
+        if up_to_date {
+            return;
+        }
+        self.matches = find_matches(display_list, &self.query);
+        self.current = self.current.min(self.matches.len().saturating_sub(1));
+        self.searched = Some((self.query.clone(), generation));
+    }
+
+    pub fn matches(&self) -> &[Selection] {
+        &self.matches[..]
+    }
+
+    pub fn current(&self) -> Option<&Selection> {
+        self.matches.get(self.current)
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    /**
+     * Move to the next match, or the previous one, wrapping around at either end
+     */
+    pub fn step(&mut self, backwards: bool) {
+        let count = self.matches.len();
+        if count == 0 {
+            return;
+        }
+        self.current = if backwards {
+            (self.current + count - 1) % count
+        } else {
+            (self.current + 1) % count
+        };
+    }
+}
+
+/**
+ * Find every case-insensitive occurrence of the query in the page's text. Words are searched as
+ * if separated by single spaces, so a query can span several items.
+ */
+pub fn find_matches(display_list: &[DisplayListItem], query: &str) -> Vec<Selection> {
+    let needle: Vec<char> = query.trim().chars().flat_map(char::to_lowercase).collect();
+    if needle.is_empty() {
+        return Vec::new();
+    }
+
+    // Lowercasing can turn one character into several, so every lowered character remembers
+    // which original character it came from
+    let mut haystack = Vec::new();
+    let mut starts = Vec::new();
+    let mut ends = Vec::new();
+    for (index, item) in display_list.iter().enumerate() {
+        if index > 0 {
+            haystack.push(' ');
+            let after_previous = ends.last().copied().unwrap_or(TextPosition {
+                item: index,
+                offset: 0,
+            });
+            starts.push(TextPosition {
+                item: index,
+                offset: 0,
+            });
+            ends.push(after_previous);
+        }
+        for (offset, c) in item.text().chars().enumerate() {
+            for lowered in c.to_lowercase() {
+                haystack.push(lowered);
+                starts.push(TextPosition {
+                    item: index,
+                    offset,
+                });
+                ends.push(TextPosition {
+                    item: index,
+                    offset: offset + 1,
+                });
+            }
+        }
+    }
+
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start + needle.len() <= haystack.len() {
+        if haystack[start..start + needle.len()] == needle[..] {
+            matches.push(Selection {
+                anchor: starts[start],
+                focus: ends[start + needle.len() - 1],
+            });
+            start += needle.len();
+        } else {
+            start += 1;
+        }
+    }
+    matches
+}
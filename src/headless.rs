@@ -0,0 +1,267 @@
+use std::{fmt::Write, sync::Arc, thread, time::Duration};
+
+use eframe::egui::{self, Context, Pos2, RawInput, Rect, TexturesDelta, Vec2};
+use image::RgbaImage;
+
+use shelves::{
+    client::Client,
+    images::Images,
+    layout::{document_height, Content, DisplayListItem},
+    url::Url,
+    Page,
+};
+
+use crate::raster::{self, Textures};
+
+/**
+ * How often to check on image fetches while waiting for them
+ */
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DumpFormat {
+    Text,
+    Json,
+}
+
+impl DumpFormat {
+    pub fn parse(value: &str) -> Option<DumpFormat> {
+        match value {
+            "text" => Some(DumpFormat::Text),
+            "json" => Some(DumpFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * A page loaded and laid out without a window
+ */
+pub struct Rendered {
+    pub title: Option<String>,
+    pub display_list: Vec<DisplayListItem>,
+    ctx: Context,
+    /// Where the page's top left corner sat in the window
+    origin: Vec2,
+    /// The textures uploaded while laying out, including the font atlas
+    textures: TexturesDelta,
+    /// Keeps the images' textures alive while the display list refers to them
+    _images: Images,
+}
+
+/**
+ * Load a page, wait for it and its images, and lay it out as if in a window `width` points wide.
+ * egui's built-in fonts are used at one pixel per point, so the layout is the same on any machine.
+ */
+pub fn render(url: &Url, width: f32, height: f32) -> anyhow::Result<Rendered> {
+    let ctx = Context::default();
+    let client = Arc::new(Client::new());
+    let page = Page::load_with(&client, url)?;
+    let mut images = Images::new();
+    images.request_all(&ctx, &client, url, &page.format_tokens);
+    while images.is_loading() {
+        images.poll(&ctx);
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    let input = RawInput {
+        screen_rect: Some(Rect::from_min_size(Pos2::ZERO, Vec2::new(width, height))),
+        ..Default::default()
+    };
+    let mut display_list = Vec::new();
+    let mut origin = Vec2::ZERO;
+    let output = ctx.run(input, |ctx| {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            origin = ui.min_rect().min.to_vec2();
+            display_list = page.layout(ui.ctx(), width, 1., &images);
+        });
+    });
+
+    Ok(Rendered {
+        title: page.title,
+        display_list,
+        ctx,
+        origin,
+        textures: output.textures_delta,
+        _images: images,
+    })
+}
+
+/**
+ * Paint the whole page, not just the first screenful, into an image in software. The window is
+ * as wide as the page was laid out for and tall enough to leave the same margin at the bottom as
+ * at the top.
+ */
+pub fn screenshot(rendered: &Rendered, width: f32) -> RgbaImage {
+    let height = (document_height(&rendered.display_list) + rendered.origin.y * 2.).ceil();
+    let width = width.ceil();
+    let input = RawInput {
+        screen_rect: Some(Rect::from_min_size(Pos2::ZERO, Vec2::new(width, height))),
+        ..Default::default()
+    };
+    let output = rendered.ctx.run(input, |ctx| {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let origin = ui.min_rect().min.to_vec2();
+            for item in rendered.display_list.iter() {
+                item.paint(ui.painter(), origin);
+            }
+        });
+    });
+
+    let mut textures = Textures::default();
+    textures.update(&rendered.textures);
+    textures.update(&output.textures_delta);
+    let primitives = rendered
+        .ctx
+        .tessellate(output.shapes, output.pixels_per_point);
+    raster::rasterize(&primitives, &textures, width as u32, height as u32)
+}
+
+/**
+ * Write out the display list, one item per line or as a JSON document
+ */
+pub fn dump(rendered: &Rendered, format: DumpFormat) -> String {
+    let title = rendered.title.as_deref();
+    match format {
+        DumpFormat::Text => dump_text(title, &rendered.display_list),
+        DumpFormat::Json => dump_json(title, &rendered.display_list),
+    }
+}
+
+fn dump_text(title: Option<&str>, display_list: &[DisplayListItem]) -> String {
+    let mut out = String::new();
+    if let Some(title) = title {
+        writeln!(out, "title {title:?}").unwrap();
+    }
+    for item in display_list.iter() {
+        let rect = item.rect();
+        write!(
+            out,
+            "{} x={:.2} y={:.2} w={:.2} h={:.2} block={}",
+            kind(item),
+            rect.left(),
+            rect.top(),
+            rect.width(),
+            rect.height(),
+            item.block
+        )
+        .unwrap();
+        if let Content::Text { .. } = &item.content {
+            write!(out, " {:?}", item.text()).unwrap();
+        }
+        if let Some(link) = &item.link {
+            write!(out, " link={link:?}").unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn dump_json(title: Option<&str>, display_list: &[DisplayListItem]) -> String {
+    let mut out = String::from("{\n  \"title\": ");
+    match title {
+        Some(title) => json_string(&mut out, title),
+        None => out.push_str("null"),
+    }
+    out.push_str(",\n  \"items\": [");
+    for (index, item) in display_list.iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        let rect = item.rect();
+        write!(
+            out,
+            "\n    {{\"kind\": \"{}\", \"x\": {:.2}, \"y\": {:.2}, \"width\": {:.2}, \"height\": {:.2}, \"block\": {}",
+            kind(item),
+            rect.left(),
+            rect.top(),
+            rect.width(),
+            rect.height(),
+            item.block
+        )
+        .unwrap();
+        if let Content::Text { .. } = &item.content {
+            out.push_str(", \"text\": ");
+            json_string(&mut out, item.text());
+        }
+        if let Some(link) = &item.link {
+            out.push_str(", \"link\": ");
+            json_string(&mut out, link);
+        }
+        out.push('}');
+    }
+    out.push_str("\n  ]\n}\n");
+    out
+}
+
+fn kind(item: &DisplayListItem) -> &'static str {
+    match item.content {
+        Content::Text { .. } => "text",
+        Content::Image { .. } => "image",
+        Content::Rule(_) => "rule",
+    }
+}
+
+fn json_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use shelves::shaping::FixedWidth;
+
+    use super::*;
+
+    fn display_list(html: &str) -> Vec<DisplayListItem> {
+        Page::parse(html).layout(&FixedWidth, 200., 1., &Images::new())
+    }
+
+    #[test]
+    fn dumps_text() {
+        let display_list = display_list("<p>Hi <a href=\"/x\">there</a><hr>");
+        assert_eq!(
+            dump_text(Some("A \"page\""), &display_list),
+            r#"title "A \"page\""
+text x=13.00 y=19.20 w=16.00 h=16.00 block=1 "Hi"
+text x=37.00 y=19.20 w=40.00 h=16.00 block=1 "there" link="/x"
+rule x=13.00 y=65.00 w=174.00 h=2.00 block=2
+"#
+        );
+    }
+
+    #[test]
+    fn dumps_json() {
+        let display_list = display_list("<p>Hi <a href=\"/x\">there</a><hr>");
+        assert_eq!(
+            dump_json(None, &display_list),
+            r#"{
+  "title": null,
+  "items": [
+    {"kind": "text", "x": 13.00, "y": 19.20, "width": 16.00, "height": 16.00, "block": 1, "text": "Hi"},
+    {"kind": "text", "x": 37.00, "y": 19.20, "width": 40.00, "height": 16.00, "block": 1, "text": "there", "link": "/x"},
+    {"kind": "rule", "x": 13.00, "y": 65.00, "width": 174.00, "height": 2.00, "block": 2}
+  ]
+}
+"#
+        );
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        let mut out = String::new();
+        json_string(&mut out, "say \"hi\"\\\n\r\t\u{1}\u{1f} caf\u{e9}");
+        assert_eq!(out, r#""say \"hi\"\\\n\r\t\u0001\u001f café""#);
+    }
+}
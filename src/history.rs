@@ -0,0 +1,70 @@
+use eframe::egui::Vec2;
+
+use shelves::url::Url;
+
+pub struct HistoryEntry {
+    pub url: Url,
+    pub scroll: Vec2,
+}
+
+/**
+ * The back/forward stack for a single navigation session
+ */
+#[derive(Default)]
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    current: Option<usize>,
+}
+
+impl History {
+    pub fn current(&self) -> Option<&HistoryEntry> {
+        self.current.map(|current| &self.entries[current])
+    }
+
+    /**
+     * Visit a new page, dropping any entries ahead of the current one
+     */
+    pub fn push(&mut self, url: Url, scroll: Vec2) {
+        let next = match self.current {
+            Some(current) => {
+                self.entries[current].scroll = scroll;
+                current + 1
+            }
+            None => 0,
+        };
+        self.entries.truncate(next);
+        self.entries.push(HistoryEntry {
+            url,
+            scroll: Vec2::ZERO,
+        });
+        self.current = Some(next);
+    }
+
+    pub fn can_go_back(&self) -> bool {
+        self.current.is_some_and(|current| current > 0)
+    }
+
+    pub fn can_go_forward(&self) -> bool {
+        self.current
+            .is_some_and(|current| current + 1 < self.entries.len())
+    }
+
+    /**
+     * The index and entry `offset` steps from the current one, without moving there. The move
+     * happens with `go_to` once the page has actually loaded.
+     */
+    pub fn peek(&self, offset: isize) -> Option<(usize, &HistoryEntry)> {
+        let index = self.current?.checked_add_signed(offset)?;
+        Some((index, self.entries.get(index)?))
+    }
+
+    /**
+     * Move to an existing entry, remembering how far the current one was scrolled
+     */
+    pub fn go_to(&mut self, index: usize, scroll: Vec2) {
+        if let Some(current) = self.current {
+            self.entries[current].scroll = scroll;
+        }
+        self.current = Some(index);
+    }
+}
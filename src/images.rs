@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use anyhow::anyhow;
+use eframe::egui::{ColorImage, Context, TextureHandle, TextureOptions};
+use image::DynamicImage;
+
+use crate::{
+    client::{CancelHandle, Client},
+    layout::FormatToken,
+    url::Url,
+};
+
+/**
+ * How many images one page fetches at once. The rest wait their turn.
+ */
+const MAX_WORKERS: usize = 4;
+
+pub enum ImageState {
+    Loading,
+    Loaded(TextureHandle),
+    Failed,
+}
+
+/**
+ * The images used by one page, keyed by the `src` attribute they were requested with
+ */
+pub struct Images {
+    images: HashMap<String, ImageState>,
+    sender: Sender<(String, anyhow::Result<ColorImage>)>,
+    receiver: Receiver<(String, anyhow::Result<ColorImage>)>,
+    /// Images waiting for a worker, taken by whichever worker is free first
+    jobs: Sender<(String, Url)>,
+    queue: Arc<Mutex<Receiver<(String, Url)>>>,
+    workers: usize,
+    /// Stops the workers once the page, and so these images, are gone
+    cancel: CancelHandle,
+}
+
+impl Default for Images {
+    fn default() -> Images {
+        Images::new()
+    }
+}
+
+impl Images {
+    pub fn new() -> Images {
+        let (sender, receiver) = mpsc::channel();
+        let (jobs, queue) = mpsc::channel();
+        Images {
+            images: HashMap::new(),
+            sender,
+            receiver,
+            jobs,
+            queue: Arc::new(Mutex::new(queue)),
+            workers: 0,
+            cancel: CancelHandle::default(),
+        }
+    }
+
+    pub fn get(&self, src: &str) -> Option<&ImageState> {
+        self.images.get(src)
+    }
+
+    /**
+     * Whether any requested image is still being fetched or decoded
+     */
+    pub fn is_loading(&self) -> bool {
+        self.images
+            .values()
+            .any(|state| matches!(state, ImageState::Loading))
+    }
+
+    /**
+     * Queue an image to be fetched and decoded on a worker thread, unless it has already been
+     * requested
+     */
+    pub fn request(&mut self, ctx: &Context, client: &Arc<Client>, base: &Url, src: &str) {
+        if self.images.contains_key(src) {
+            return;
+        }
+        let Ok(url) = base.resolve(src) else {
+            self.images.insert(src.to_string(), ImageState::Failed);
+            return;
+        };
+        self.images.insert(src.to_string(), ImageState::Loading);
+        // Nothing can have hung up, since `self` holds the queue's receiver too
+        self.jobs.send((src.to_string(), url)).unwrap();
+
+        if self.workers < MAX_WORKERS {
+            self.workers += 1;
+            let ctx = ctx.clone();
+            let client = client.clone();
+            let sender = self.sender.clone();
+            let queue = self.queue.clone();
+            let cancel = self.cancel.clone();
+            thread::spawn(move || {
+                loop {
+                    // The lock is only held while waiting for the next job, not while fetching it
+                    let job = queue.lock().unwrap().recv();
+                    // Once the images are dropped, queued jobs are abandoned and the queue closes
+                    let Ok((src, url)) = job else {
+                        return;
+                    };
+                    if cancel.is_cancelled() {
+                        return;
+                    }
+                    let max_side = ctx.input(|input| input.max_texture_side);
+                    let image = fetch_image(&client, &url, &cancel, max_side);
+                    // The page may have been navigated away from, in which case nobody wants it
+                    let _ = sender.send((src, image));
+                    ctx.request_repaint();
+                }
+            });
+        }
+    }
+
+    /**
+     * Queue every image the given tokens use, resolving their addresses against `base`
+     */
+    pub fn request_all(
+        &mut self,
+        ctx: &Context,
+        client: &Arc<Client>,
+        base: &Url,
+        tokens: &[FormatToken],
+    ) {
+        for token in tokens {
+            if let FormatToken::Image { src, .. } = token {
+                self.request(ctx, client, base, src);
+            }
+        }
+    }
+
+    /**
+     * Upload any images that finished decoding. Returns true if any image finished, loaded or not,
+     * since the page needs to be laid out again.
+     */
+    pub fn poll(&mut self, ctx: &Context) -> bool {
+        let mut changed = false;
+        for (src, image) in self.receiver.try_iter() {
+            // A broken image is drawn as its alt text, which is all the page needs to know
+            let state = match image {
+                Ok(image) => {
+                    ImageState::Loaded(ctx.load_texture(&src, image, TextureOptions::LINEAR))
+                }
+                Err(_) => ImageState::Failed,
+            };
+            self.images.insert(src, state);
+            changed = true;
+        }
+        changed
+    }
+}
+
+impl Drop for Images {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+fn fetch_image(
+    client: &Client,
+    url: &Url,
+    cancel: &CancelHandle,
+    max_side: usize,
+) -> anyhow::Result<ColorImage> {
+    let resp = client.request_cancellable(url, cancel)?;
+    if resp.status_code >= 400 {
+        return Err(anyhow!("server returned {}", resp.status_code));
+    }
+    let image = image::load_from_memory(resp.body.as_bytes()?)?;
+    Ok(fit_texture(image, max_side))
+}
+
+/**
+ * Convert a decoded image for upload, shrinking it to keep its aspect ratio if either side is
+ * longer than the renderer allows a texture to be
+ */
+fn fit_texture(image: DynamicImage, max_side: usize) -> ColorImage {
+    let max_side = u32::try_from(max_side).unwrap_or(u32::MAX);
+    let image = if image.width() > max_side || image.height() > max_side {
+        image.thumbnail(max_side, max_side)
+    } else {
+        image
+    }
+    .to_rgba8();
+    let size = [image.width() as usize, image.height() as usize];
+    ColorImage::from_rgba_unmultiplied(size, image.as_raw())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, time::Duration};
+
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use image::{ImageFormat, RgbaImage};
+
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        RgbaImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn oversized_images_are_shrunk_to_fit_a_texture() {
+        let image = image::load_from_memory(&png(3000, 2)).unwrap();
+        assert_eq!(fit_texture(image, 2048).size, [2048, 1]);
+        let image = image::load_from_memory(&png(30, 20)).unwrap();
+        assert_eq!(fit_texture(image, 2048).size, [30, 20]);
+    }
+
+    #[test]
+    fn oversized_images_load() {
+        let ctx = Context::default();
+        let client = Arc::new(Client::new());
+        let base = Url::new("http://example.org/".to_string()).unwrap();
+        let src = format!(
+            "data:image/png;base64,{}",
+            BASE64_STANDARD.encode(png(3000, 2))
+        );
+        let mut images = Images::new();
+        images.request(&ctx, &client, &base, &src);
+        while images.is_loading() {
+            images.poll(&ctx);
+            thread::sleep(Duration::from_millis(1));
+        }
+        let max_side = ctx.input(|input| input.max_texture_side);
+        let Some(ImageState::Loaded(texture)) = images.get(&src) else {
+            panic!("the image didn't load");
+        };
+        assert!(texture.size().into_iter().all(|side| side <= max_side));
+    }
+}
@@ -1,138 +1,1935 @@
-use std::sync::Arc;
+use std::{collections::HashMap, ops::Range, sync::Arc};
 
 use eframe::{
-    egui::{text::LayoutJob, Color32, FontFamily, FontId, Galley, TextFormat, Ui},
-    epaint::Pos2,
+    egui::{text::LayoutJob, Color32, FontFamily, FontId, Galley, Painter, TextFormat, TextureId},
+    epaint::{Pos2, Rect, Vec2},
 };
+use unicode_linebreak::linebreaks;
+use unicode_segmentation::UnicodeSegmentation;
 
-use crate::parser::Token;
+use crate::{
+    bidi::{self, Direction, Reordered},
+    images::{ImageState, Images},
+    list::ListStyle,
+    parser::{Tag, Token, SOFT_HYPHEN},
+    shaping::TextShaper,
+    style, table,
+};
 
 pub const HSTEP: f32 = 13.;
 pub const VSTEP: f32 = 15.;
 
+/**
+ * How many spaces wide an empty text input is drawn
+ */
+const INPUT_WIDTH_CHARS: usize = 20;
+
+/**
+ * How far each level of list is indented, in CSS pixels
+ */
+const LIST_INDENT: f32 = 40.;
+
+/**
+ * How much `<small>` and `<big>` scale the font size by
+ */
+const SMALLER: f32 = 0.83;
+const LARGER: f32 = 1.2;
+
+/**
+ * The range of font sizes text is drawn at, however deeply it is nested in tags that scale it.
+ * The largest glyphs still have to fit in the font atlas.
+ */
+const MIN_FONT_SIZE: f32 = 1.;
+const MAX_FONT_SIZE: f32 = 500.;
+
+/**
+ * The font size and vertical margin of each heading level, as multiples of the surrounding font
+ * size, following the sizes browsers use by default
+ */
+const HEADINGS: [(f32, f32); 6] = [
+    (2., 0.67),
+    (1.5, 0.83),
+    (1.17, 1.),
+    (1., 1.33),
+    (0.83, 1.67),
+    (0.67, 2.33),
+];
+
+/**
+ * Elements that never have contents or a closing tag
+ */
+const VOID_ELEMENTS: [&str; 6] = ["br", "hr", "img", "input", "meta", "link"];
+
+/**
+ * Elements that start and end on a line of their own, without any margin
+ */
+const PLAIN_BLOCKS: [&str; 11] = [
+    "div",
+    "section",
+    "article",
+    "header",
+    "footer",
+    "nav",
+    "main",
+    "aside",
+    "address",
+    "form",
+    "figcaption",
+];
+
 pub enum FormatToken {
-    Text { layout: LayoutJob },
+    Text {
+        layout: LayoutJob,
+        link: Option<String>,
+        element: Option<ElementId>,
+    },
+    Image {
+        src: String,
+        width: Option<f32>,
+        height: Option<f32>,
+        /// Shown in place of the image if it fails to load
+        alt: LayoutJob,
+        link: Option<String>,
+        element: Option<ElementId>,
+    },
     Linebreak,
+    /// Start a new line unless already at the start of one
+    BlockBreak,
+    /// Vertical space between blocks, in CSS pixels. Adjacent margins collapse into the largest.
+    Margin(f32),
+    /// A horizontal rule across the page
+    Rule,
+    /// How the following lines share out the width they don't fill
+    Align(TextAlign),
+    /// Where the following items sit on their line
+    VerticalAlign(VerticalAlign),
+    /// Where the following words may be broken when they don't fit on a line
+    WordBreak(WordBreak),
+    /// The base direction of the following lines
+    Direction(Direction),
+    /// Move the left edge of the following lines by this many CSS pixels
+    Indent(f32),
+    /// The bullet or number in front of a list item. The number is worked out during layout,
+    /// since a reversed list counts down from its length, which isn't known until it closes.
+    ListMarker {
+        format: TextFormat,
+        style: ListStyle,
+        list: ListId,
+        index: usize,
+        start: Option<i64>,
+        reversed: bool,
+    },
+    /// The tokens up to the matching `TableEnd` make up a table, and are laid out as a grid
+    TableStart(TableStyle),
+    RowStart,
+    CellStart {
+        colspan: usize,
+        rowspan: usize,
+    },
+    CellEnd,
+    TableEnd,
 }
 
-pub fn format_tokens(tokens: &[Token]) -> Vec<FormatToken> {
-    let mut italics = false;
-    let mut bold = false;
-    let mut size = 16.0;
+pub type ListId = usize;
 
-    let mut format_tokens = Vec::new();
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TextAlign {
+    Left,
+    Right,
+    Center,
+    Justify,
+    /// Left in left-to-right text and right in right-to-left text
+    Start,
+    End,
+}
 
-    for token in tokens.iter() {
-        match token {
-            Token::Tag(tag) => match tag.as_str() {
-                "i" => italics = true,
-                "/i" => italics = false,
-                "b" => bold = true,
-                "/b" => bold = false,
-                "br" | "/br" | "/p" => {
-                    format_tokens.push(FormatToken::Linebreak);
+impl TextAlign {
+    /**
+     * Parse a `text-align` value or `align` attribute
+     */
+    pub fn parse(value: &str) -> Option<TextAlign> {
+        Some(match value.trim().to_ascii_lowercase().as_str() {
+            "left" => TextAlign::Left,
+            "right" => TextAlign::Right,
+            "start" => TextAlign::Start,
+            "end" => TextAlign::End,
+            "center" | "middle" => TextAlign::Center,
+            "justify" => TextAlign::Justify,
+            _ => return None,
+        })
+    }
+
+    /**
+     * Turn `Start` and `End` into a side, for a line running in the given direction
+     */
+    fn resolve(self, rtl: bool) -> TextAlign {
+        match (self, rtl) {
+            (TextAlign::Start, false) | (TextAlign::End, true) => TextAlign::Left,
+            (TextAlign::Start, true) | (TextAlign::End, false) => TextAlign::Right,
+            (align, _) => align,
+        }
+    }
+}
+
+/**
+ * Where an item sits vertically on its line. Lengths are in CSS pixels.
+ */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum VerticalAlign {
+    /// On the line's baseline, raised by the given amount or lowered if it's negative
+    Baseline(f32),
+    /// With its middle the given height above the baseline
+    Middle(f32),
+    /// With its top at the top of the line
+    Top,
+    /// With its bottom at the bottom of the line
+    Bottom,
+}
+
+impl Default for VerticalAlign {
+    fn default() -> VerticalAlign {
+        VerticalAlign::Baseline(0.)
+    }
+}
+
+/**
+ * Where words may be broken besides at soft hyphens
+ */
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct WordBreak {
+    /// Between any two characters, from `word-break: break-all`
+    pub anywhere: bool,
+    /// Between any two characters when a word doesn't fit on a line by itself, from
+    /// `overflow-wrap: break-word`
+    pub overflow: bool,
+}
+
+/**
+ * The spacing and borders of a table, in CSS pixels
+ */
+pub struct TableStyle {
+    pub border: f32,
+    pub padding: f32,
+    pub spacing: f32,
+}
+
+/**
+ * Identifies a focusable element (a link, input or button). Ids are handed out in document order,
+ * so they double as the tab order.
+ */
+pub type ElementId = usize;
+
+/**
+ * Turns tokens into format tokens, keeping the text style between calls so a document can be
+ * formatted as it streams in
+ */
+pub struct Formatter {
+    italics: bool,
+    bold: bool,
+    size: f32,
+    link: Option<String>,
+    element: Option<ElementId>,
+    next_element: ElementId,
+    in_title: bool,
+    title: Vec<String>,
+    lists: Vec<ListState>,
+    next_list: ListId,
+    /// The font size and boldness to go back to when the open heading closes
+    heading: Option<(f32, bool)>,
+    /// The boldness to go back to when the open <th> closes
+    header_cell: Option<bool>,
+    /// The elements that set the text alignment and are still open, innermost last
+    alignments: Vec<(String, TextAlign)>,
+    /// The open inline elements that can move text up or down, with the font size to go back to
+    /// when each closes
+    vertical_aligns: Vec<(String, VerticalAlign, f32)>,
+    /// The open <small> and <big> elements, with the font size to go back to when each closes
+    font_sizes: Vec<(String, f32)>,
+    /// The open elements that changed a style their contents inherit
+    inherited: Vec<(String, Inherited)>,
+}
+
+/**
+ * The styles that an element's contents inherit from it and that the formatter keeps track of
+ */
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+struct Inherited {
+    word_break: WordBreak,
+    direction: Direction,
+}
+
+/**
+ * A <ul> or <ol> that hasn't been closed yet
+ */
+struct ListState {
+    id: ListId,
+    style: ListStyle,
+    start: Option<i64>,
+    reversed: bool,
+    items: usize,
+}
+
+impl Default for Formatter {
+    fn default() -> Formatter {
+        Formatter::new()
+    }
+}
+
+impl Formatter {
+    pub fn new() -> Formatter {
+        Formatter {
+            italics: false,
+            bold: false,
+            size: 16.0,
+            link: None,
+            element: None,
+            next_element: 0,
+            in_title: false,
+            title: Vec::new(),
+            lists: Vec::new(),
+            next_list: 0,
+            heading: None,
+            header_cell: None,
+            alignments: Vec::new(),
+            vertical_aligns: Vec::new(),
+            font_sizes: Vec::new(),
+            inherited: Vec::new(),
+        }
+    }
+
+    /**
+     * The document's <title>, once it has been closed
+     */
+    pub fn title(&self) -> Option<String> {
+        if self.in_title || self.title.is_empty() {
+            None
+        } else {
+            Some(self.title.join(" "))
+        }
+    }
+
+    pub fn format(&mut self, tokens: &[Token]) -> Vec<FormatToken> {
+        let mut format_tokens = Vec::new();
+
+        for token in tokens.iter() {
+            match token {
+                Token::Tag(tag) => {
+                    let mut tag = Tag::parse(tag);
+                    // Inherited styles apply to the element's own line breaks, so they start
+                    // before it's handled and end after
+                    let closing = tag.name.starts_with('/');
+                    if !closing {
+                        self.update_inherited(&tag, &mut format_tokens);
+                    }
+                    match tag.name.as_str() {
+                        "i" => self.italics = true,
+                        "/i" => self.italics = false,
+                        "b" => self.bold = true,
+                        "/b" => self.bold = false,
+                        "br" | "/br" => {
+                            format_tokens.push(FormatToken::Linebreak);
+                        }
+                        "p" => {
+                            format_tokens.push(FormatToken::BlockBreak);
+                            format_tokens.push(FormatToken::Margin(self.size));
+                            // Paragraphs can't nest, so a new one ends any that's still open
+                            self.close_block("p", &mut format_tokens);
+                            self.open_block(&tag, None, &mut format_tokens);
+                        }
+                        "/p" => {
+                            format_tokens.push(FormatToken::BlockBreak);
+                            self.close_block("p", &mut format_tokens);
+                            format_tokens.push(FormatToken::Margin(self.size));
+                        }
+                        name if PLAIN_BLOCKS.contains(&name) || name == "center" => {
+                            format_tokens.push(FormatToken::BlockBreak);
+                            let default = (name == "center").then_some(TextAlign::Center);
+                            self.open_block(&tag, default, &mut format_tokens);
+                        }
+                        name if name.strip_prefix('/').is_some_and(|open| {
+                            PLAIN_BLOCKS.contains(&open) || open == "center"
+                        }) =>
+                        {
+                            format_tokens.push(FormatToken::BlockBreak);
+                            self.close_block(closing_name(name), &mut format_tokens);
+                        }
+                        "blockquote" => {
+                            format_tokens.push(FormatToken::BlockBreak);
+                            format_tokens.push(FormatToken::Margin(self.size));
+                            format_tokens.push(FormatToken::Indent(LIST_INDENT));
+                            self.open_block(&tag, None, &mut format_tokens);
+                        }
+                        "/blockquote" => {
+                            format_tokens.push(FormatToken::BlockBreak);
+                            self.close_block("blockquote", &mut format_tokens);
+                            format_tokens.push(FormatToken::Indent(-LIST_INDENT));
+                            format_tokens.push(FormatToken::Margin(self.size));
+                        }
+                        "hr" => {
+                            format_tokens.push(FormatToken::BlockBreak);
+                            format_tokens.push(FormatToken::Margin(self.size / 2.));
+                            format_tokens.push(FormatToken::Rule);
+                            format_tokens.push(FormatToken::Margin(self.size / 2.));
+                        }
+                        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                            format_tokens.push(FormatToken::BlockBreak);
+                            let (scale, margin) = HEADINGS[heading_level(&tag.name)];
+                            if self.heading.is_none() {
+                                self.heading = Some((self.size, self.bold));
+                            }
+                            self.size *= scale;
+                            self.bold = true;
+                            format_tokens.push(FormatToken::Margin(self.size * margin));
+                            self.open_block(&tag, None, &mut format_tokens);
+                        }
+                        "/h1" | "/h2" | "/h3" | "/h4" | "/h5" | "/h6" => {
+                            format_tokens.push(FormatToken::BlockBreak);
+                            let name = closing_name(&tag.name);
+                            self.close_block(name, &mut format_tokens);
+                            let (_, margin) = HEADINGS[heading_level(name)];
+                            format_tokens.push(FormatToken::Margin(self.size * margin));
+                            if let Some((size, bold)) = self.heading.take() {
+                                self.size = size;
+                                self.bold = bold;
+                            }
+                        }
+                        "sup" | "sub" | "span" => {
+                            let default = match tag.name.as_str() {
+                                "sup" => Some("super"),
+                                "sub" => Some("sub"),
+                                _ => None,
+                            };
+                            let css = tag.attributes.get("style").and_then(|declarations| {
+                                style::parse_declarations(declarations).remove("vertical-align")
+                            });
+                            let vertical_align = css
+                                .as_deref()
+                                .or(default)
+                                .and_then(|value| self.parse_vertical_align(value))
+                                .unwrap_or(self.vertical_align());
+                            self.vertical_aligns.push((
+                                tag.name.clone(),
+                                vertical_align,
+                                self.size,
+                            ));
+                            if default.is_some() {
+                                // Like `font-size: smaller`
+                                self.size *= 0.83;
+                            }
+                            format_tokens.push(FormatToken::VerticalAlign(vertical_align));
+                        }
+                        "/sup" | "/sub" | "/span" => {
+                            let name = closing_name(&tag.name);
+                            if let Some(position) = self
+                                .vertical_aligns
+                                .iter()
+                                .rposition(|(open, _, _)| open == name)
+                            {
+                                self.size = self.vertical_aligns[position].2;
+                                self.vertical_aligns.truncate(position);
+                                format_tokens
+                                    .push(FormatToken::VerticalAlign(self.vertical_align()));
+                            }
+                        }
+                        "small" | "big" => {
+                            self.font_sizes.push((tag.name.clone(), self.size));
+                            // Like `font-size: smaller` and `larger`, kept to sizes that can be drawn
+                            let scale = if tag.name == "small" { SMALLER } else { LARGER };
+                            self.size = (self.size * scale).clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+                        }
+                        "/small" | "/big" => {
+                            let name = closing_name(&tag.name);
+                            if let Some(position) =
+                                self.font_sizes.iter().rposition(|(open, _)| open == name)
+                            {
+                                self.size = self.font_sizes[position].1;
+                                self.font_sizes.truncate(position);
+                            }
+                        }
+                        "ul" | "ol" => {
+                            format_tokens.push(FormatToken::BlockBreak);
+                            // Nested lists sit snugly inside their item
+                            if self.lists.is_empty() {
+                                format_tokens.push(FormatToken::Margin(self.size));
+                            }
+                            format_tokens.push(FormatToken::Indent(LIST_INDENT));
+                            let list = self.open_list(&tag);
+                            self.lists.push(list);
+                        }
+                        "/ul" | "/ol" => {
+                            format_tokens.push(FormatToken::BlockBreak);
+                            if self.lists.pop().is_some() {
+                                format_tokens.push(FormatToken::Indent(-LIST_INDENT));
+                            }
+                            if self.lists.is_empty() {
+                                format_tokens.push(FormatToken::Margin(self.size));
+                            }
+                        }
+                        "li" => {
+                            format_tokens.push(FormatToken::BlockBreak);
+                            format_tokens.push(self.list_marker());
+                        }
+                        "/li" => format_tokens.push(FormatToken::BlockBreak),
+                        "table" => {
+                            format_tokens.push(FormatToken::BlockBreak);
+                            let number = |name: &str| {
+                                tag.attributes
+                                    .get(name)
+                                    .and_then(|value| value.trim_end_matches("px").parse().ok())
+                            };
+                            let border = match tag.attributes.get("border") {
+                                // A bare `border` attribute asks for the thinnest one
+                                Some(_) => number("border").unwrap_or(1.),
+                                None => 0.,
+                            };
+                            format_tokens.push(FormatToken::TableStart(TableStyle {
+                                border,
+                                padding: number("cellpadding").unwrap_or(1.),
+                                spacing: number("cellspacing").unwrap_or(2.),
+                            }));
+                        }
+                        "/table" => {
+                            self.end_cell(&mut format_tokens);
+                            format_tokens.push(FormatToken::TableEnd);
+                        }
+                        "tr" => {
+                            self.end_cell(&mut format_tokens);
+                            format_tokens.push(FormatToken::RowStart);
+                        }
+                        "td" | "th" => {
+                            self.end_cell(&mut format_tokens);
+                            let span = |name: &str| {
+                                tag.attributes
+                                    .get(name)
+                                    .and_then(|value| value.trim().parse::<u64>().ok())
+                            };
+                            // Clamped as HTML does, so a hostile page can't make a huge grid
+                            let colspan = match span("colspan") {
+                                None | Some(0) => 1,
+                                Some(colspan) => colspan.min(table::MAX_COLSPAN as u64) as usize,
+                            };
+                            let rowspan = span("rowspan").map_or(1, |rowspan| {
+                                rowspan.min(table::MAX_ROWSPAN as u64) as usize
+                            });
+                            format_tokens.push(FormatToken::CellStart { colspan, rowspan });
+                            // Header cells are bold and centered unless told otherwise
+                            let header = tag.name == "th";
+                            if header {
+                                self.header_cell = Some(self.bold);
+                                self.bold = true;
+                            }
+                            let default = header.then_some(TextAlign::Center);
+                            self.open_block(&tag, default, &mut format_tokens);
+                        }
+                        "/td" | "/th" | "/tr" => {
+                            self.end_cell(&mut format_tokens);
+                            format_tokens.push(FormatToken::CellEnd);
+                        }
+                        "title" => self.in_title = true,
+                        "/title" => self.in_title = false,
+                        "a" => {
+                            self.link = tag.attributes.remove("href");
+                            if self.link.is_some() {
+                                self.element = Some(self.new_element());
+                            }
+                        }
+                        "/a" => {
+                            self.link = None;
+                            self.element = None;
+                        }
+                        "button" => self.element = Some(self.new_element()),
+                        "/button" => self.element = None,
+                        "input"
+                            if tag
+                                .attributes
+                                .get("type")
+                                .is_some_and(|kind| kind.eq_ignore_ascii_case("hidden")) => {}
+                        "input" => {
+                            let element = self.new_element();
+                            let value = tag
+                                .attributes
+                                .remove("value")
+                                .or_else(|| tag.attributes.remove("placeholder"))
+                                .filter(|value| !value.is_empty())
+                                .unwrap_or_else(|| " ".repeat(INPUT_WIDTH_CHARS));
+                            let mut job = self.text_job(&value);
+                            job.sections[0].format.background = Color32::from_gray(48);
+                            format_tokens.push(FormatToken::Text {
+                                layout: job,
+                                link: None,
+                                element: Some(element),
+                            });
+                        }
+                        "img" => {
+                            let Some(src) = tag.attributes.remove("src") else {
+                                continue;
+                            };
+                            let dimension = |value: Option<&String>| {
+                                value.and_then(|value| value.trim_end_matches("px").parse().ok())
+                            };
+                            // Images also take the legacy `align` attribute, though only the values
+                            // that don't float the image
+                            let vertical_align = tag
+                                .attributes
+                                .get("style")
+                                .and_then(|declarations| {
+                                    style::parse_declarations(declarations).remove("vertical-align")
+                                })
+                                .or_else(|| {
+                                    tag.attributes.get("align").map(|align| {
+                                        match align.to_ascii_lowercase().as_str() {
+                                            "absmiddle" => "middle".to_string(),
+                                            "texttop" => "top".to_string(),
+                                            "absbottom" => "bottom".to_string(),
+                                            other => other.to_string(),
+                                        }
+                                    })
+                                })
+                                .and_then(|value| self.parse_vertical_align(&value));
+                            if let Some(vertical_align) = vertical_align {
+                                format_tokens.push(FormatToken::VerticalAlign(vertical_align));
+                            }
+                            format_tokens.push(FormatToken::Image {
+                                src,
+                                width: dimension(tag.attributes.get("width")),
+                                height: dimension(tag.attributes.get("height")),
+                                alt: self
+                                    .text_job(tag.attributes.get("alt").map_or("", String::as_str)),
+                                link: self.link.clone(),
+                                element: self.element,
+                            });
+                            if vertical_align.is_some() {
+                                format_tokens
+                                    .push(FormatToken::VerticalAlign(self.vertical_align()));
+                            }
+                        }
+                        _ => {}
+                    }
+                    if closing {
+                        self.update_inherited(&tag, &mut format_tokens);
+                    }
                 }
-                "small" => size -= 2.0,
-                "/small" => size += 2.0,
-                "big" => size += 4.0,
-                "/big" => size -= 4.0,
-                _ => {}
-            },
-            Token::Word(word) => {
-                if word.is_empty() {
-                    continue;
+                Token::Word(word) => {
+                    if word.is_empty() {
+                        continue;
+                    }
+                    if self.in_title {
+                        self.title.push(word.replace(SOFT_HYPHEN, ""));
+                        continue;
+                    }
+                    format_tokens.push(FormatToken::Text {
+                        layout: self.text_job(word),
+                        link: self.link.clone(),
+                        element: self.element,
+                    });
+                }
+            }
+        }
+
+        format_tokens
+    }
+
+    /**
+     * Undo the style a table cell applied, since cells close implicitly when the next one opens
+     */
+    fn end_cell(&mut self, format_tokens: &mut Vec<FormatToken>) {
+        if let Some(bold) = self.header_cell.take() {
+            self.bold = bold;
+        }
+        if let Some((name, _)) = self.alignments.last() {
+            if name == "td" || name == "th" {
+                let name = name.clone();
+                // The cell's last line keeps its alignment
+                format_tokens.push(FormatToken::BlockBreak);
+                self.close_block(&name, format_tokens);
+            }
+        }
+    }
+
+    /**
+     * Start aligning text the way a block element asks to, from its `text-align` style or its
+     * legacy `align` attribute
+     */
+    fn open_block(
+        &mut self,
+        tag: &Tag,
+        default: Option<TextAlign>,
+        format_tokens: &mut Vec<FormatToken>,
+    ) {
+        let css = tag.attributes.get("style").and_then(|declarations| {
+            style::parse_declarations(declarations)
+                .get("text-align")
+                .and_then(|value| TextAlign::parse(value))
+        });
+        let attribute = tag
+            .attributes
+            .get("align")
+            .and_then(|value| TextAlign::parse(value));
+        if let Some(align) = css.or(attribute).or(default) {
+            self.alignments.push((tag.name.clone(), align));
+            format_tokens.push(FormatToken::Align(align));
+        }
+    }
+
+    /**
+     * Go back to the alignment from before the innermost open element with this name
+     */
+    fn close_block(&mut self, name: &str, format_tokens: &mut Vec<FormatToken>) {
+        if let Some(position) = self.alignments.iter().rposition(|(open, _)| open == name) {
+            self.alignments.truncate(position);
+            let align = self
+                .alignments
+                .last()
+                .map_or(TextAlign::Start, |(_, align)| *align);
+            format_tokens.push(FormatToken::Align(align));
+        }
+    }
+
+    fn inherited_style(&self) -> Inherited {
+        self.inherited
+            .last()
+            .map_or(Inherited::default(), |(_, inherited)| *inherited)
+    }
+
+    /**
+     * Follow the `word-break` and `overflow-wrap` styles and `dir` attribute of elements as they
+     * open and close. Any element can set them, so this happens before the element itself is
+     * handled.
+     */
+    fn update_inherited(&mut self, tag: &Tag, format_tokens: &mut Vec<FormatToken>) {
+        let before = self.inherited_style();
+        if let Some(name) = tag.name.strip_prefix('/') {
+            if let Some(position) = self.inherited.iter().rposition(|(open, _)| open == name) {
+                self.inherited.truncate(position);
+                self.push_inherited_changes(before, format_tokens);
+            }
+            return;
+        }
+        if VOID_ELEMENTS.contains(&tag.name.as_str()) {
+            return;
+        }
+
+        let mut inherited = before;
+        if let Some(declarations) = tag.attributes.get("style") {
+            let declarations = style::parse_declarations(declarations);
+            if let Some(value) = declarations.get("word-break") {
+                inherited.word_break.anywhere = value.eq_ignore_ascii_case("break-all");
+            }
+            // `word-wrap` is the old name for `overflow-wrap`
+            let overflow_wrap = declarations
+                .get("overflow-wrap")
+                .or_else(|| declarations.get("word-wrap"));
+            if let Some(value) = overflow_wrap {
+                inherited.word_break.overflow = value.eq_ignore_ascii_case("break-word")
+                    || value.eq_ignore_ascii_case("anywhere");
+            }
+        }
+        if let Some(direction) = tag
+            .attributes
+            .get("dir")
+            .and_then(|dir| Direction::parse(dir))
+        {
+            inherited.direction = direction;
+        }
+        if inherited != before {
+            self.inherited.push((tag.name.clone(), inherited));
+            self.push_inherited_changes(before, format_tokens);
+        }
+    }
+
+    fn push_inherited_changes(&self, before: Inherited, format_tokens: &mut Vec<FormatToken>) {
+        let after = self.inherited_style();
+        if after.word_break != before.word_break {
+            format_tokens.push(FormatToken::WordBreak(after.word_break));
+        }
+        if after.direction != before.direction {
+            format_tokens.push(FormatToken::Direction(after.direction));
+        }
+    }
+
+    fn vertical_align(&self) -> VerticalAlign {
+        self.vertical_aligns
+            .last()
+            .map_or(VerticalAlign::default(), |(_, vertical_align, _)| {
+                *vertical_align
+            })
+    }
+
+    /**
+     * Parse a `vertical-align` value for an element inside the current one. Raising and lowering
+     * adds to how far the current element is already raised, so superscripts can nest.
+     */
+    fn parse_vertical_align(&self, value: &str) -> Option<VerticalAlign> {
+        let raised = match self.vertical_align() {
+            VerticalAlign::Baseline(raised) => raised,
+            _ => 0.,
+        };
+        let value = value.trim().to_ascii_lowercase();
+        let raise = match value.as_str() {
+            "baseline" => 0.,
+            "super" => self.size / 3.,
+            "sub" => -self.size / 5.,
+            // Half the height of a lowercase letter, roughly
+            "middle" => return Some(VerticalAlign::Middle(raised + self.size / 4.)),
+            "top" | "text-top" => return Some(VerticalAlign::Top),
+            "bottom" | "text-bottom" => return Some(VerticalAlign::Bottom),
+            length => {
+                if let Some(px) = length.strip_suffix("px") {
+                    px.trim().parse().ok()?
+                } else if let Some(em) = length.strip_suffix("em") {
+                    em.trim().parse::<f32>().ok()? * self.size
+                } else if let Some(percent) = length.strip_suffix('%') {
+                    // Percentages are of the line height, which is about 1.2 times the font size
+                    percent.trim().parse::<f32>().ok()? / 100. * self.size * 1.2
+                } else {
+                    length.parse().ok().filter(|length: &f32| *length == 0.)?
                 }
-                let mut job = LayoutJob::default();
-                // TODO: bold isn't
-                job.append(
-                    word,
-                    0.,
-                    TextFormat {
-                        font_id: FontId::new(size, FontFamily::Proportional),
-                        // TODO: this is no good, don't hardcode colors
-                        color: if bold {
-                            Color32::WHITE
-                        } else {
-                            Color32::LIGHT_GRAY
-                        },
-                        italics,
-                        ..Default::default()
-                    },
-                );
-                format_tokens.push(FormatToken::Text { layout: job });
             }
+        };
+        Some(VerticalAlign::Baseline(raised + raise))
+    }
+
+    fn new_element(&mut self) -> ElementId {
+        let element = self.next_element;
+        self.next_element += 1;
+        element
+    }
+
+    fn open_list(&mut self, tag: &Tag) -> ListState {
+        let id = self.next_list;
+        self.next_list += 1;
+        let ordered = tag.name == "ol";
+        let default = if ordered {
+            ListStyle::Decimal
+        } else {
+            ListStyle::unordered(self.lists.len())
+        };
+        let css = tag.attributes.get("style").and_then(|declarations| {
+            style::parse_declarations(declarations)
+                .get("list-style-type")
+                .and_then(|value| ListStyle::from_css(value))
+        });
+        let attribute = tag
+            .attributes
+            .get("type")
+            .and_then(|value| ListStyle::from_type_attribute(value));
+        ListState {
+            id,
+            style: css.or(attribute).unwrap_or(default),
+            start: tag
+                .attributes
+                .get("start")
+                .and_then(|start| start.trim().parse().ok())
+                .filter(|_| ordered),
+            reversed: ordered && tag.attributes.contains_key("reversed"),
+            items: 0,
         }
     }
 
-    format_tokens
+    /**
+     * The marker for the next item of the innermost open list. A stray <li> still gets a bullet,
+     * as if it were in a list of its own.
+     */
+    fn list_marker(&mut self) -> FormatToken {
+        let format = self.text_format();
+        let Some(list) = self.lists.last_mut() else {
+            let list = self.next_list;
+            self.next_list += 1;
+            return FormatToken::ListMarker {
+                format,
+                style: ListStyle::unordered(0),
+                list,
+                index: 0,
+                start: None,
+                reversed: false,
+            };
+        };
+        let index = list.items;
+        list.items += 1;
+        FormatToken::ListMarker {
+            format,
+            style: list.style,
+            list: list.id,
+            index,
+            start: list.start,
+            reversed: list.reversed,
+        }
+    }
+
+    fn text_job(&self, text: &str) -> LayoutJob {
+        let mut job = LayoutJob::default();
+        job.append(text, 0., self.text_format());
+        job
+    }
+
+    fn text_format(&self) -> TextFormat {
+        // TODO: bold isn't
+        TextFormat {
+            font_id: FontId::new(
+                self.size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE),
+                FontFamily::Proportional,
+            ),
+            // TODO: this is no good, don't hardcode colors
+            color: if self.link.is_some() {
+                Color32::LIGHT_BLUE
+            } else if self.bold {
+                Color32::WHITE
+            } else {
+                Color32::LIGHT_GRAY
+            },
+            italics: self.italics,
+            ..Default::default()
+        }
+    }
+}
+
+/**
+ * The element a closing tag like "/p" ends
+ */
+fn closing_name(name: &str) -> &str {
+    name.strip_prefix('/').unwrap_or(name)
+}
+
+/**
+ * The index into `HEADINGS` for a heading tag like "h2"
+ */
+fn heading_level(name: &str) -> usize {
+    name.strip_prefix('h')
+        .and_then(|level| level.parse::<usize>().ok())
+        .map_or(0, |level| level.clamp(1, 6) - 1)
+}
+
+pub enum Content {
+    /// A word or part of one. Right-to-left text is drawn in a different order from the one it's
+    /// read in, which is kept in `reordered`.
+    Text {
+        galley: Arc<Galley>,
+        reordered: Option<Reordered>,
+    },
+    /// An image, or the space reserved for one that hasn't loaded yet
+    Image {
+        texture: Option<TextureId>,
+        size: Vec2,
+    },
+    Rule(Vec2),
 }
 
 pub struct DisplayListItem {
     pub pos: Pos2,
-    pub galley: Arc<Galley>,
+    pub content: Content,
+    pub link: Option<String>,
+    pub element: Option<ElementId>,
+    /// Which run of text between hard line breaks this item belongs to
+    pub block: usize,
+}
+
+impl Content {
+    pub fn size(&self) -> Vec2 {
+        match self {
+            Content::Text { galley, .. } => galley.size(),
+            Content::Image { size, .. } => *size,
+            Content::Rule(size) => *size,
+        }
+    }
 }
 
-pub fn layout(ui: &Ui, tokens: &[FormatToken]) -> Vec<DisplayListItem> {
-    let mut display_list = Vec::new();
-    let mut line_buffer = Vec::new();
+impl DisplayListItem {
+    pub fn size(&self) -> Vec2 {
+        self.content.size()
+    }
+
+    pub fn rect(&self) -> Rect {
+        Rect::from_min_size(self.pos, self.size())
+    }
+
+    pub fn galley(&self) -> Option<&Arc<Galley>> {
+        match &self.content {
+            Content::Text { galley, .. } => Some(galley),
+            Content::Image { .. } | Content::Rule(_) => None,
+        }
+    }
 
-    let mut cursor = Pos2::new(0., 0.);
+    /**
+     * The item's text in the order it's read in, which is empty for images
+     */
+    pub fn text(&self) -> &str {
+        match &self.content {
+            Content::Text {
+                reordered: Some(reordered),
+                ..
+            } => &reordered.logical,
+            Content::Text { galley, .. } => &galley.job.text,
+            Content::Image { .. } | Content::Rule(_) => "",
+        }
+    }
+
+    /**
+     * Where a character offset into `text` is drawn, as a character offset into the galley
+     */
+    pub fn visual_offset(&self, offset: usize) -> usize {
+        match &self.content {
+            Content::Text {
+                reordered: Some(reordered),
+                ..
+            } => reordered.visual_offset(offset),
+            _ => offset,
+        }
+    }
+
+    /**
+     * The character offset into `text` for a character offset into the galley
+     */
+    pub fn logical_offset(&self, offset: usize) -> usize {
+        match &self.content {
+            Content::Text {
+                reordered: Some(reordered),
+                ..
+            } => reordered.logical_offset(offset),
+            _ => offset,
+        }
+    }
+
+    /**
+     * Draw the item, moved by the given offset from page to screen coordinates
+     */
+    pub fn paint(&self, painter: &Painter, offset: Vec2) {
+        let rect = self.rect().translate(offset);
+        match &self.content {
+            Content::Text { galley, .. } => {
+                painter.galley(rect.min, galley.clone(), Default::default());
+            }
+            Content::Image {
+                texture: Some(texture),
+                ..
+            } => {
+                painter.image(
+                    *texture,
+                    rect,
+                    Rect::from_min_max(Pos2::ZERO, Pos2::new(1., 1.)),
+                    Color32::WHITE,
+                );
+            }
+            Content::Image { texture: None, .. } => {
+                let stroke = painter
+                    .ctx()
+                    .style()
+                    .visuals
+                    .widgets
+                    .noninteractive
+                    .bg_stroke;
+                painter.rect_stroke(rect, 0., stroke);
+            }
+            Content::Rule(_) => {
+                painter.rect_filled(rect, 0., Color32::GRAY);
+            }
+        }
+    }
+
+    /**
+     * How far the item reaches above the baseline. Images sit on the baseline.
+     */
+    fn ascent(&self) -> f32 {
+        match &self.content {
+            Content::Text { galley, .. } => galley_max_ascent(galley).unwrap_or(0.0),
+            Content::Image { size, .. } | Content::Rule(size) => size.y,
+        }
+    }
+
+    fn descent(&self) -> f32 {
+        match &self.content {
+            Content::Text { galley, .. } => {
+                galley.mesh_bounds.bottom() - galley.mesh_bounds.center().y
+            }
+            Content::Image { .. } | Content::Rule(_) => 0.0,
+        }
+    }
+}
+
+/**
+ * Lay the page out to fit `width`. The zoom factor scales every length, including font sizes, so
+ * zooming wraps lines again rather than just magnifying them.
+ */
+pub fn layout(
+    shaper: &dyn TextShaper,
+    width: f32,
+    tokens: &[FormatToken],
+    zoom: f32,
+    images: &Images,
+) -> Vec<DisplayListItem> {
+    let mut list_lengths: HashMap<ListId, i64> = HashMap::new();
     for token in tokens.iter() {
+        if let FormatToken::ListMarker { list, .. } = token {
+            *list_lengths.entry(*list).or_default() += 1;
+        }
+    }
+
+    let mut layout = BlockLayout {
+        shaper,
+        zoom,
+        images,
+        list_lengths: &list_lengths,
+        width,
+        edge: HSTEP * zoom,
+        left: 0.,
+        align: TextAlign::Start,
+        margin: 0.,
+        cursor: Pos2::new(HSTEP * zoom, 0.),
+        block: 0,
+        vertical_align: VerticalAlign::default(),
+        word_break: WordBreak::default(),
+        direction: Direction::default(),
+        line_buffer: Vec::new(),
+        display_list: Vec::new(),
+    };
+    layout.tokens(tokens);
+    layout.flush();
+
+    layout.display_list
+}
+
+/**
+ * The size to draw an image at in CSS pixels. A missing width or height is filled in from the
+ * other using the image's aspect ratio.
+ */
+fn image_size(intrinsic: Option<Vec2>, width: Option<f32>, height: Option<f32>) -> Option<Vec2> {
+    match (intrinsic, width, height) {
+        (_, Some(width), Some(height)) => Some(Vec2::new(width, height)),
+        (Some(intrinsic), Some(width), None) if intrinsic.x > 0. => {
+            Some(Vec2::new(width, width * intrinsic.y / intrinsic.x))
+        }
+        (Some(intrinsic), None, Some(height)) if intrinsic.y > 0. => {
+            Some(Vec2::new(height * intrinsic.x / intrinsic.y, height))
+        }
+        (Some(intrinsic), _, _) => Some(intrinsic),
+        (None, _, _) => None,
+    }
+}
+
+/**
+ * A table cell and the range of the table's tokens that make up its contents
+ */
+struct TableCell {
+    colspan: usize,
+    rowspan: usize,
+    tokens: Range<usize>,
+}
+
+/**
+ * The index of the `TableEnd` that closes a table whose contents start at `start`
+ */
+fn table_end(tokens: &[FormatToken], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (index, token) in tokens.iter().enumerate().skip(start) {
         match token {
-            FormatToken::Text { layout } => {
-                let font = layout
-                    .sections
-                    .first()
-                    .map(|section| section.format.font_id.clone())
-                    .unwrap_or_default();
-                let space = ui
-                    .painter()
-                    .layout_no_wrap(" ".to_string(), font, Color32::BLACK);
-                let galley = ui.painter().layout_job(layout.clone());
-                let word_width = galley.rect.width();
-                if cursor.x + word_width > ui.min_rect().width() {
-                    flush(&mut line_buffer, &mut display_list, &mut cursor);
-                }
-                line_buffer.push(DisplayListItem {
-                    pos: cursor,
-                    galley,
+            FormatToken::TableStart(_) => depth += 1,
+            FormatToken::TableEnd if depth == 0 => return Some(index),
+            FormatToken::TableEnd => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/**
+ * Split a table's contents into rows of cells. Like in HTML, content outside of any cell gets a
+ * cell of its own, and a cell outside of any row starts one.
+ */
+fn table_rows(tokens: &[FormatToken]) -> Vec<Vec<TableCell>> {
+    let mut rows: Vec<Vec<TableCell>> = Vec::new();
+    let mut open: Option<TableCell> = None;
+    let close = |rows: &mut Vec<Vec<TableCell>>, open: &mut Option<TableCell>, end| {
+        if let Some(mut cell) = open.take() {
+            cell.tokens.end = end;
+            if rows.is_empty() {
+                rows.push(Vec::new());
+            }
+            rows.last_mut().unwrap().push(cell);
+        }
+    };
+    let implicit = |index| TableCell {
+        colspan: 1,
+        rowspan: 1,
+        tokens: index..index,
+    };
+
+    let mut index = 0;
+    while index < tokens.len() {
+        match &tokens[index] {
+            FormatToken::RowStart => {
+                close(&mut rows, &mut open, index);
+                rows.push(Vec::new());
+            }
+            FormatToken::CellStart { colspan, rowspan } => {
+                close(&mut rows, &mut open, index);
+                open = Some(TableCell {
+                    colspan: *colspan,
+                    rowspan: *rowspan,
+                    tokens: index + 1..index + 1,
                 });
-                cursor.x += word_width + space.rect.width();
             }
-            FormatToken::Linebreak => {
-                flush(&mut line_buffer, &mut display_list, &mut cursor);
+            FormatToken::CellEnd => close(&mut rows, &mut open, index),
+            FormatToken::TableStart(_) => {
+                open.get_or_insert_with(|| implicit(index));
+                index = table_end(tokens, index + 1).unwrap_or(tokens.len());
+            }
+            FormatToken::Text { .. } | FormatToken::Image { .. } => {
+                open.get_or_insert_with(|| implicit(index));
             }
+            _ => {}
         }
+        index += 1;
     }
+    close(&mut rows, &mut open, tokens.len());
+    rows
+}
 
-    flush(&mut line_buffer, &mut display_list, &mut cursor);
+/**
+ * The state of laying out a run of lines
+ */
+struct BlockLayout<'a> {
+    shaper: &'a dyn TextShaper,
+    zoom: f32,
+    images: &'a Images,
+    /// How many items each list has, for numbering reversed lists
+    list_lengths: &'a HashMap<ListId, i64>,
+    width: f32,
+    /// The space kept clear at either side, which is the page margin or nothing inside a table cell
+    edge: f32,
+    /// How far lines are indented past the page margin, in CSS pixels
+    left: f32,
+    align: TextAlign,
+    /// The vertical margin already added since the last line, so adjacent margins collapse
+    margin: f32,
+    cursor: Pos2,
+    block: usize,
+    vertical_align: VerticalAlign,
+    word_break: WordBreak,
+    direction: Direction,
+    /// The items on the current line, and how each sits on it
+    line_buffer: Vec<(DisplayListItem, VerticalAlign)>,
+    display_list: Vec<DisplayListItem>,
+}
 
-    display_list
+impl<'a> BlockLayout<'a> {
+    fn tokens(&mut self, tokens: &[FormatToken]) {
+        let mut index = 0;
+        while index < tokens.len() {
+            match &tokens[index] {
+                FormatToken::Text {
+                    layout: job,
+                    link,
+                    element,
+                } => self.text(job, link, *element),
+                FormatToken::Image {
+                    src,
+                    width,
+                    height,
+                    alt,
+                    link,
+                    element,
+                } => self.image(src, *width, *height, alt, link, *element),
+                FormatToken::Linebreak => {
+                    self.flush();
+                    self.block += 1;
+                }
+                FormatToken::BlockBreak => {
+                    if !self.line_buffer.is_empty() {
+                        self.flush();
+                    }
+                    self.block += 1;
+                }
+                FormatToken::Margin(margin) => self.margin(*margin),
+                FormatToken::Align(align) => self.align = *align,
+                FormatToken::VerticalAlign(vertical_align) => self.vertical_align = *vertical_align,
+                FormatToken::WordBreak(word_break) => self.word_break = *word_break,
+                FormatToken::Direction(direction) => self.direction = *direction,
+                FormatToken::Rule => self.rule(),
+                FormatToken::Indent(indent) => {
+                    self.left = (self.left + indent).max(0.);
+                    if self.line_buffer.is_empty() {
+                        self.cursor.x = self.line_start();
+                    }
+                }
+                FormatToken::ListMarker {
+                    format,
+                    style,
+                    list,
+                    index,
+                    start,
+                    reversed,
+                } => {
+                    // Saturating, since `start` can be anything a page puts in the attribute
+                    let index = *index as i64;
+                    let number = if *reversed {
+                        start
+                            .unwrap_or(self.list_lengths[list])
+                            .saturating_sub(index)
+                    } else {
+                        start.unwrap_or(1).saturating_add(index)
+                    };
+                    if let Some(marker) = style.marker(number) {
+                        self.marker(marker, format.clone());
+                    }
+                }
+                FormatToken::TableStart(style) => {
+                    // A table still streaming in has no end yet, so lay out what there is of it
+                    let end = table_end(tokens, index + 1).unwrap_or(tokens.len());
+                    self.table(style, &tokens[index + 1..end]);
+                    index = end;
+                }
+                FormatToken::RowStart
+                | FormatToken::CellStart { .. }
+                | FormatToken::CellEnd
+                | FormatToken::TableEnd => {}
+            }
+            index += 1;
+        }
+    }
+
+    fn image(
+        &mut self,
+        src: &str,
+        width: Option<f32>,
+        height: Option<f32>,
+        alt: &LayoutJob,
+        link: &Option<String>,
+        element: Option<ElementId>,
+    ) {
+        let image = self.images.get(src);
+        let texture = match image {
+            Some(ImageState::Loaded(texture)) => Some(texture),
+            _ => None,
+        };
+        let intrinsic = texture.map(|texture| texture.size_vec2());
+        let size = image_size(intrinsic, width, height);
+        match size {
+            // Without an image or dimensions to reserve space with, show the alt text
+            Some(size) if !matches!(image, Some(ImageState::Failed)) => {
+                let size = size * self.zoom;
+                let max_width = self.width - 2. * self.edge;
+                let size = if size.x > max_width && max_width > 0. {
+                    size * (max_width / size.x)
+                } else {
+                    size
+                };
+                let space = self.space_width(&FontId::default());
+                self.push(
+                    Content::Image {
+                        texture: texture.map(|texture| texture.id()),
+                        size,
+                    },
+                    space,
+                    link,
+                    element,
+                );
+            }
+            _ if alt.text.is_empty() => {}
+            _ => self.text(alt, link, element),
+        }
+    }
+
+    /**
+     * Lay out tokens on their own, as the contents of a table cell `width` wide
+     */
+    fn cell(&self, tokens: &[FormatToken], width: f32, block: usize) -> BlockLayout<'a> {
+        let mut layout = BlockLayout {
+            shaper: self.shaper,
+            zoom: self.zoom,
+            images: self.images,
+            list_lengths: self.list_lengths,
+            width,
+            edge: 0.,
+            left: 0.,
+            align: TextAlign::Start,
+            margin: 0.,
+            cursor: Pos2::ZERO,
+            block,
+            vertical_align: VerticalAlign::default(),
+            word_break: self.word_break,
+            direction: self.direction,
+            line_buffer: Vec::new(),
+            display_list: Vec::new(),
+        };
+        layout.tokens(tokens);
+        layout.flush();
+        layout
+    }
+
+    fn table(&mut self, style: &TableStyle, tokens: &[FormatToken]) {
+        if !self.line_buffer.is_empty() {
+            self.flush();
+        }
+        let rows = table_rows(tokens);
+        let (spans, column_count) = table::place_cells(
+            &rows
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|cell| (cell.colspan, cell.rowspan))
+                        .collect()
+                })
+                .collect::<Vec<_>>(),
+        );
+        let cells: Vec<_> = rows.iter().flatten().zip(spans).collect();
+
+        let border = style.border * self.zoom;
+        // Tables with a border draw one around each cell too
+        let cell_border = if style.border > 0. {
+            self.zoom.max(1.)
+        } else {
+            0.
+        };
+        let inset = style.padding * self.zoom + cell_border;
+        let spacing = style.spacing * self.zoom;
+
+        let measured: Vec<_> = cells
+            .iter()
+            .map(|(cell, span)| {
+                let items = self.cell(&tokens[cell.tokens.clone()], f32::INFINITY, 0);
+                let items = &items.display_list;
+                let min = items.iter().map(|item| item.size().x).fold(0., f32::max);
+                let max = items
+                    .iter()
+                    .map(|item| item.rect().right())
+                    .fold(0., f32::max);
+                (*span, min + 2. * inset, max + 2. * inset)
+            })
+            .collect();
+        let available = self.width - self.edge - self.cursor.x - 2. * border;
+        let widths = table::column_widths(column_count, &measured, available, spacing);
+
+        let mut laid_out = Vec::new();
+        for (cell, span) in cells.iter() {
+            let width = table::extent(&widths[span.column..span.column + span.columns], spacing);
+            let layout = self.cell(
+                &tokens[cell.tokens.clone()],
+                (width - 2. * inset).max(0.),
+                self.block,
+            );
+            self.block = layout.block + 1;
+            let height = document_height(&layout.display_list) + 2. * inset;
+            laid_out.push((*span, width, height, layout.display_list));
+        }
+        let heights = table::row_heights(
+            rows.len(),
+            &laid_out
+                .iter()
+                .map(|(span, _, height, _)| (*span, *height))
+                .collect::<Vec<_>>(),
+            spacing,
+        );
+
+        let size = Vec2::new(
+            table::extent(&widths, spacing),
+            table::extent(&heights, spacing),
+        ) + Vec2::splat(2. * (border + spacing));
+        // Like <center> in other browsers, alignment moves whole tables too
+        let leftover = (self.width - self.edge - self.cursor.x - size.x).max(0.);
+        let shift = match self.align.resolve(self.direction == Direction::Rtl) {
+            TextAlign::Right => leftover,
+            TextAlign::Center => leftover / 2.,
+            _ => 0.,
+        };
+        let origin = self.cursor + Vec2::new(shift, 0.);
+        let inner = origin + Vec2::splat(border + spacing);
+        let offset = |sizes: &[f32], index: usize| {
+            sizes[..index]
+                .iter()
+                .map(|size| size + spacing)
+                .sum::<f32>()
+        };
+        for (span, width, content_height, items) in laid_out {
+            let height = table::extent(&heights[span.row..span.row + span.rows], spacing);
+            let cell = Rect::from_min_size(
+                inner + Vec2::new(offset(&widths, span.column), offset(&heights, span.row)),
+                Vec2::new(width, height),
+            );
+            if cell_border > 0. {
+                self.outline(cell, cell_border);
+            }
+            // Cell contents are centered vertically, as they are by default in HTML
+            let content = cell.min + Vec2::new(inset, inset + (height - content_height) / 2.);
+            for mut item in items {
+                item.pos += content.to_vec2();
+                self.display_list.push(item);
+            }
+        }
+
+        let table = Rect::from_min_size(origin, size);
+        if border > 0. {
+            self.outline(table, border);
+        }
+        self.cursor = Pos2::new(self.line_start(), table.bottom() + VSTEP * self.zoom);
+        self.margin = 0.;
+    }
+
+    /**
+     * Draw a border just inside a rectangle, out of four rules
+     */
+    fn outline(&mut self, rect: Rect, width: f32) {
+        let sides = [
+            Rect::from_min_size(rect.min, Vec2::new(rect.width(), width)),
+            Rect::from_min_size(
+                rect.left_bottom() - Vec2::new(0., width),
+                Vec2::new(rect.width(), width),
+            ),
+            Rect::from_min_size(rect.min, Vec2::new(width, rect.height())),
+            Rect::from_min_size(
+                rect.right_top() - Vec2::new(width, 0.),
+                Vec2::new(width, rect.height()),
+            ),
+        ];
+        for side in sides {
+            self.display_list.push(DisplayListItem {
+                pos: side.min,
+                content: Content::Rule(side.size()),
+                link: None,
+                element: None,
+                block: self.block,
+            });
+        }
+    }
+
+    fn text(&mut self, job: &LayoutJob, link: &Option<String>, element: Option<ElementId>) {
+        let mut job = job.clone();
+        for section in job.sections.iter_mut() {
+            section.format.font_id.size *= self.zoom;
+        }
+        let font = job
+            .sections
+            .first()
+            .map(|section| section.format.font_id.clone())
+            .unwrap_or_default();
+        let space = self.space_width(&font);
+
+        // Where the word may be broken, as the end of the part before the break, where the part
+        // after it starts, and whether the break is shown with a hyphen. Besides soft hyphens,
+        // these are the line break opportunities of Unicode's line breaking algorithm, which
+        // matter most for scripts like Chinese that don't put spaces between words.
+        let mut breaks: Vec<(usize, usize, bool)> = Vec::new();
+        for (offset, _) in linebreaks(&job.text) {
+            if offset == job.text.len() {
+                continue;
+            }
+            let before = job.text[..offset].strip_suffix(SOFT_HYPHEN);
+            match before {
+                Some(before) => breaks.push((before.len(), offset, true)),
+                None => breaks.push((offset, offset, false)),
+            }
+        }
+
+        // Break the word over several lines if it doesn't fit on this one, and split it between
+        // any two characters if there's nowhere else to and the style allows it
+        let mut start = 0;
+        loop {
+            let available = self.width - self.edge - self.cursor.x;
+            let rest = self.piece(&job, start..job.text.len(), false);
+            if rest.size().x <= available {
+                self.push(rest, space, link, element);
+                return;
+            }
+
+            let fresh_line = self.cursor.x <= self.line_start();
+            // Longer pieces are wider, so search for the last break that leaves a piece that fits
+            let candidates: Vec<_> = breaks.iter().filter(|(end, _, _)| *end > start).collect();
+            let fitting = candidates.partition_point(|(end, _, hyphen)| {
+                self.piece(&job, start..*end, *hyphen).size().x <= available
+            });
+            if fitting > 0 {
+                let (end, next, hyphen) = *candidates[fitting - 1];
+                let piece = self.piece(&job, start..end, hyphen);
+                self.push(piece, 0., link, element);
+                self.wrap();
+                start = next;
+                continue;
+            }
+
+            let split = self.word_break.anywhere || (self.word_break.overflow && fresh_line);
+            if split {
+                let end = self.fitting_graphemes(&job, start, available, fresh_line);
+                if end > start {
+                    let piece = self.piece(&job, start..end, false);
+                    self.push(piece, 0., link, element);
+                    start = end;
+                }
+                self.wrap();
+            } else if fresh_line {
+                // Nothing for it but to overflow
+                self.push(rest, space, link, element);
+                return;
+            } else {
+                self.wrap();
+            }
+        }
+    }
+
+    /**
+     * Lay out part of a word, leaving out any soft hyphens and ending it with a real one if asked.
+     * Right-to-left text is put in the order it's drawn in, remembering the order it's read in.
+     */
+    fn piece(&self, job: &LayoutJob, range: Range<usize>, hyphen: bool) -> Content {
+        let mut piece = LayoutJob::default();
+        let mut logical = String::new();
+        let mut order = Vec::new();
+        let mut reordered = false;
+        for section in job.sections.iter() {
+            let start = section.byte_range.start.max(range.start);
+            let end = section.byte_range.end.min(range.end);
+            if start < end {
+                let text = job.text[start..end].replace(SOFT_HYPHEN, "");
+                let chars: Vec<char> = text.chars().collect();
+                let offset = order.len();
+                match bidi::visual_order(&text, self.direction) {
+                    Some(section_order) => {
+                        let visual: String = section_order
+                            .iter()
+                            .map(|(index, _)| chars[*index])
+                            .collect();
+                        piece.append(&visual, 0., section.format.clone());
+                        order.extend(
+                            section_order
+                                .into_iter()
+                                .map(|(index, rtl)| (offset + index, rtl)),
+                        );
+                        reordered = true;
+                    }
+                    None => {
+                        piece.append(&text, 0., section.format.clone());
+                        order.extend((offset..offset + chars.len()).map(|index| (index, false)));
+                    }
+                }
+                logical.push_str(&text);
+            }
+        }
+        if hyphen {
+            if let Some(section) = job.sections.last() {
+                piece.append("-", 0., section.format.clone());
+                logical.push('-');
+                order.push((order.len(), false));
+            }
+        }
+        Content::Text {
+            galley: self.shaper.shape(piece),
+            reordered: reordered.then_some(Reordered { logical, order }),
+        }
+    }
+
+    /**
+     * The end of the longest run of whole graphemes from `start` that fits in the available width.
+     * At the start of a line at least one grapheme is taken, so that breaking always progresses.
+     */
+    fn fitting_graphemes(
+        &self,
+        job: &LayoutJob,
+        start: usize,
+        available: f32,
+        fresh_line: bool,
+    ) -> usize {
+        let boundaries: Vec<usize> = job.text[start..]
+            .grapheme_indices(true)
+            .map(|(offset, grapheme)| start + offset + grapheme.len())
+            .collect();
+        // Widths only grow as graphemes are added, so search for the last one that fits
+        let fitting = boundaries
+            .partition_point(|end| self.piece(job, start..*end, false).size().x <= available);
+        match fitting {
+            0 if fresh_line => boundaries.first().copied().unwrap_or(start),
+            0 => start,
+            fitting => boundaries[fitting - 1],
+        }
+    }
+
+    /**
+     * Hang a list marker in the indent to the left of the current line, without moving the cursor
+     */
+    fn marker(&mut self, text: String, mut format: TextFormat) {
+        format.font_id.size *= self.zoom;
+        let space = self.space_width(&format.font_id);
+        let galley = self.shaper.shape(LayoutJob::single_section(text, format));
+        let x = (self.cursor.x - galley.size().x - space).max(0.);
+        let marker = DisplayListItem {
+            pos: Pos2::new(x, self.cursor.y),
+            content: Content::Text {
+                galley,
+                reordered: None,
+            },
+            link: None,
+            element: None,
+            block: self.block,
+        };
+        self.line_buffer.push((marker, VerticalAlign::default()));
+    }
+
+    /**
+     * Leave a gap before the next line, unless an earlier margin already left as much
+     */
+    fn margin(&mut self, margin: f32) {
+        if !self.line_buffer.is_empty() {
+            self.flush();
+        }
+        let margin = margin * self.zoom;
+        self.cursor.y += (margin - self.margin).max(0.);
+        self.margin = self.margin.max(margin);
+    }
+
+    /**
+     * Draw a horizontal rule across the current indentation
+     */
+    fn rule(&mut self) {
+        if !self.line_buffer.is_empty() {
+            self.flush();
+        }
+        // Measuring a table cell lays it out infinitely wide, and a rule has no width of its own
+        let width = if self.width.is_finite() {
+            self.width - self.edge - self.cursor.x
+        } else {
+            0.
+        };
+        let size = Vec2::new(width.max(0.), (2. * self.zoom).max(1.));
+        self.display_list.push(DisplayListItem {
+            pos: self.cursor,
+            content: Content::Rule(size),
+            link: None,
+            element: None,
+            block: self.block,
+        });
+        self.cursor.y += size.y;
+        self.margin = 0.;
+    }
+
+    fn line_start(&self) -> f32 {
+        self.edge + self.left * self.zoom
+    }
+
+    fn space_width(&self, font: &FontId) -> f32 {
+        let format = TextFormat::simple(font.clone(), Color32::BLACK);
+        self.shaper
+            .shape(LayoutJob::single_section(" ".to_string(), format))
+            .rect
+            .width()
+    }
+
+    /**
+     * Add an item to the current line, moving to the next line first if it doesn't fit
+     */
+    fn push(
+        &mut self,
+        content: Content,
+        space: f32,
+        link: &Option<String>,
+        element: Option<ElementId>,
+    ) {
+        let mut item = DisplayListItem {
+            pos: self.cursor,
+            content,
+            link: link.clone(),
+            element,
+            block: self.block,
+        };
+        let item_width = item.size().x;
+        // Something too wide for even an empty line overflows it rather than leaving it blank
+        if self.cursor.x + item_width > self.width - self.edge && self.cursor.x > self.line_start()
+        {
+            self.wrap();
+            item.pos = self.cursor;
+        }
+        self.line_buffer.push((item, self.vertical_align));
+        self.cursor.x += item_width + space;
+    }
+
+    /**
+     * Finish the current line and start another, because the next item doesn't fit on it
+     */
+    fn wrap(&mut self) {
+        self.finish_line(true);
+    }
+
+    /**
+     * Move the items on the current line to share out the room left at its end. Justified text
+     * only stretches lines that wrapped, so the last line of a paragraph stays ragged.
+     */
+    fn align_line(&mut self, wrapped: bool, rtl: bool) {
+        let line_start = self.line_start();
+        let right = self
+            .line_buffer
+            .iter()
+            .map(|(item, _)| item.rect().right())
+            .reduce(f32::max)
+            .unwrap_or(line_start);
+        let leftover = (self.width - self.edge - right).max(0.);
+        // Measuring a table cell lays it out infinitely wide, leaving nothing to share out
+        if !leftover.is_finite() {
+            return;
+        }
+        let align = match self.align {
+            TextAlign::Justify if !wrapped => TextAlign::Start,
+            align => align,
+        };
+        match align.resolve(rtl) {
+            TextAlign::Right | TextAlign::Center => {
+                let shift = if align.resolve(rtl) == TextAlign::Right {
+                    leftover
+                } else {
+                    leftover / 2.
+                };
+                for (item, _) in self.line_buffer.iter_mut() {
+                    item.pos.x += shift;
+                }
+            }
+            TextAlign::Justify => {
+                // List markers hang to the left of the line and don't take part. Right-to-left
+                // items are no longer in logical order, so go through them from left to right.
+                let mut words: Vec<_> = self
+                    .line_buffer
+                    .iter_mut()
+                    .map(|(item, _)| item)
+                    .filter(|item| item.pos.x >= line_start)
+                    .collect();
+                if words.len() < 2 {
+                    return;
+                }
+                words.sort_by(|a, b| a.pos.x.total_cmp(&b.pos.x));
+                let extra = leftover / (words.len() - 1) as f32;
+                for (gap, item) in words.into_iter().enumerate() {
+                    item.pos.x += gap as f32 * extra;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /**
+     * Put the current line's items in the order they're drawn in, following the Unicode
+     * bidirectional algorithm. Returns whether the line runs right to left.
+     */
+    fn reorder_line(&mut self) -> bool {
+        let line_start = self.line_start();
+        // List markers hang outside the line and keep their place
+        let words: Vec<usize> = (0..self.line_buffer.len())
+            .filter(|index| self.line_buffer[*index].0.pos.x >= line_start)
+            .collect();
+        let texts: Vec<&str> = words
+            .iter()
+            .map(|index| match &self.line_buffer[*index].0 {
+                item @ DisplayListItem {
+                    content: Content::Text { .. },
+                    ..
+                } => item.text(),
+                // Images count as neutral characters, like they do in other browsers
+                _ => "\u{FFFC}",
+            })
+            .collect();
+        let (rtl, order) = bidi::line_order(&texts, self.direction);
+        let Some(order) = order else {
+            return rtl;
+        };
+
+        // The space after each item in logical order
+        let bounds: Vec<(f32, f32)> = words
+            .iter()
+            .map(|index| {
+                let rect = self.line_buffer[*index].0.rect();
+                (rect.left(), rect.width())
+            })
+            .collect();
+        let gaps: Vec<f32> = (0..bounds.len())
+            .map(|word| match bounds.get(word + 1) {
+                Some((next, _)) => next - bounds[word].0 - bounds[word].1,
+                None => 0.,
+            })
+            .collect();
+        // Neighbours in visual order are separated by the space between them in logical order, or
+        // if they weren't neighbours there, the space after whichever of them comes first
+        let mut x = line_start;
+        for (position, word) in order.iter().enumerate() {
+            self.line_buffer[words[*word]].0.pos.x = x;
+            x += bounds[*word].1;
+            if let Some(next) = order.get(position + 1) {
+                x += gaps[(*word).min(*next)];
+            }
+        }
+        rtl
+    }
+
+    fn flush(&mut self) {
+        self.finish_line(false);
+    }
+
+    /**
+     * Position the current line's items around a shared baseline and start a new line. Raised and
+     * lowered items make the line taller, and items aligned to the top or bottom of the line
+     * stretch it if they don't fit.
+     */
+    fn finish_line(&mut self, wrapped: bool) {
+        let rtl = self.reorder_line();
+        self.align_line(wrapped, rtl);
+        self.cursor.x = self.line_start();
+        let zoom = self.zoom;
+        // How far each item reaches above and below the line's baseline
+        let extents = |item: &DisplayListItem, vertical_align: VerticalAlign| match vertical_align {
+            VerticalAlign::Baseline(raise) => {
+                Some((item.ascent() + raise * zoom, item.descent() - raise * zoom))
+            }
+            VerticalAlign::Middle(center) => {
+                let half = item.size().y / 2.;
+                Some((center * zoom + half, half - center * zoom))
+            }
+            VerticalAlign::Top | VerticalAlign::Bottom => None,
+        };
+        let (max_ascent, max_descent) = self
+            .line_buffer
+            .iter()
+            .filter_map(|(item, vertical_align)| extents(item, *vertical_align))
+            .fold(
+                (0f32, 0f32),
+                |(ascent, descent), (item_ascent, item_descent)| {
+                    (ascent.max(item_ascent), descent.max(item_descent))
+                },
+            );
+        let top = self.cursor.y;
+        let baseline = top + 1.25 * max_ascent;
+        let bottom = self
+            .line_buffer
+            .iter()
+            .filter(|(_, vertical_align)| {
+                matches!(vertical_align, VerticalAlign::Top | VerticalAlign::Bottom)
+            })
+            .map(|(item, _)| top + item.size().y)
+            .fold(baseline + 1.25 * max_descent, f32::max);
+        for (mut item, vertical_align) in self.line_buffer.drain(..) {
+            item.pos.y = match extents(&item, vertical_align) {
+                Some((ascent, _)) => baseline - ascent,
+                None if vertical_align == VerticalAlign::Top => top,
+                None => bottom - item.size().y,
+            };
+            self.display_list.push(item);
+        }
+        self.cursor.y = bottom;
+        self.cursor.y += VSTEP * self.zoom;
+        self.margin = 0.;
+    }
 }
 
-fn flush(
-    line_buffer: &mut Vec<DisplayListItem>,
-    display_list: &mut Vec<DisplayListItem>,
-    cursor: &mut Pos2,
-) {
-    cursor.x = 0.0;
-    let max_ascent = line_buffer
+/**
+ * The smallest rectangle containing every item belonging to the given element
+ */
+pub fn element_bounds(display_list: &[DisplayListItem], element: ElementId) -> Option<Rect> {
+    display_list
         .iter()
-        .filter_map(|item| galley_max_ascent(&item.galley))
-        .reduce(f32::max)
-        .unwrap_or(0.0);
-    let baseline = cursor.y + 1.25 * max_ascent;
-    let max_descent = line_buffer
+        .filter(|item| item.element == Some(element))
+        .map(DisplayListItem::rect)
+        .reduce(|a, b| a.union(b))
+}
+
+/**
+ * The distance from the top of the page to the bottom of its lowest item
+ */
+pub fn document_height(display_list: &[DisplayListItem]) -> f32 {
+    display_list
         .iter()
-        .map(|item| item.galley.mesh_bounds.bottom() - item.galley.mesh_bounds.center().y)
+        .map(|item| item.pos.y + item.size().y)
         .reduce(f32::max)
-        .unwrap_or(0.0);
-    for mut word in line_buffer.drain(..) {
-        word.pos.y = baseline - galley_max_ascent(&word.galley).unwrap_or(0.0);
-        display_list.push(word);
-    }
-    cursor.y = baseline + 1.25 * max_descent;
-    cursor.y += VSTEP;
+        .unwrap_or(0.0)
 }
 
 fn galley_max_ascent(galley: &Galley) -> Option<f32> {
@@ -143,3 +1940,166 @@ fn galley_max_ascent(galley: &Galley) -> Option<f32> {
         .map(|glyph| glyph.ascent)
         .reduce(f32::max)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Lexer, shaping::FixedWidth};
+
+    /**
+     * Lay a document out with the fixed-width font, returning each text item's text and position
+     */
+    fn layout_html(html: &str, width: f32) -> Vec<(String, f32, f32)> {
+        let mut lexer = Lexer::new();
+        let mut tokens = lexer.feed(html);
+        tokens.extend(lexer.finish());
+        let tokens = Formatter::new().format(&tokens);
+        layout(&FixedWidth, width, &tokens, 1., &Images::new())
+            .iter()
+            .filter(|item| item.galley().is_some())
+            .map(|item| (item.text().to_string(), item.pos.x, item.pos.y))
+            .collect()
+    }
+
+    /**
+     * The text of each item, in display list order
+     */
+    fn layout_words(html: &str) -> Vec<String> {
+        layout_html(html, 800.)
+            .into_iter()
+            .map(|(text, _, _)| text)
+            .collect()
+    }
+
+    /**
+     * A paragraph's first line: below the paragraph's 16px margin, the baseline sits 1.25 ascents
+     * down, and 16px text has a 12.8px ascent
+     */
+    const FIRST_LINE: f32 = 16. + 1.25 * 12.8 - 12.8;
+
+    /**
+     * The distance between lines of 16px text: 1.25 ascents above the baseline, 1.25 descents of
+     * half the text's height below it, then VSTEP
+     */
+    const LINE_HEIGHT: f32 = 1.25 * 12.8 + 1.25 * 8. + VSTEP;
+
+    #[test]
+    fn words_are_separated_by_a_space() {
+        assert_eq!(
+            layout_html("<p>ab cd</p>", 800.),
+            [
+                ("ab".to_string(), HSTEP, FIRST_LINE),
+                ("cd".to_string(), HSTEP + 16. + 8., FIRST_LINE),
+            ]
+        );
+    }
+
+    #[test]
+    fn words_wrap_when_the_line_is_full() {
+        // Exactly room for "ab cd" between the page's edges
+        let width = HSTEP + 5. * 8. + HSTEP;
+        assert_eq!(
+            layout_html("<p>ab cd ef</p>", width),
+            [
+                ("ab".to_string(), HSTEP, FIRST_LINE),
+                ("cd".to_string(), HSTEP + 24., FIRST_LINE),
+                ("ef".to_string(), HSTEP, FIRST_LINE + LINE_HEIGHT),
+            ]
+        );
+    }
+
+    #[test]
+    fn centered_lines_share_out_the_leftover_width() {
+        let width = HSTEP + 40. + HSTEP;
+        assert_eq!(
+            layout_html("<p align=center>ab</p>", width),
+            [("ab".to_string(), HSTEP + (40. - 16.) / 2., FIRST_LINE)]
+        );
+    }
+
+    #[test]
+    fn skips_empty_and_non_ascii_tag_names() {
+        let words = layout_words("<p>a<>b</>c<é>d</é>e</p>");
+        assert_eq!(words, ["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn list_numbers_stop_at_the_largest_integer() {
+        let words = layout_words(
+            "<ol start=9223372036854775807><li>a<li>b</ol>\
+             <ol reversed start=-9223372036854775808><li>c<li>d</ol>",
+        );
+        let max = format!("{}.", i64::MAX);
+        let min = format!("{}.", i64::MIN);
+        assert_eq!(words, [&max, "a", &max, "b", &min, "c", &min, "d"]);
+    }
+
+    #[test]
+    fn deeply_nested_small_and_big_text_keep_a_drawable_size() {
+        let heights = |html: &str| -> Vec<f32> {
+            let mut lexer = Lexer::new();
+            let mut tokens = lexer.feed(html);
+            tokens.extend(lexer.finish());
+            let tokens = Formatter::new().format(&tokens);
+            layout(&FixedWidth, 800., &tokens, 1., &Images::new())
+                .iter()
+                .map(|item| item.rect().height())
+                .collect()
+        };
+        let html = format!(
+            "{}tiny{} normal",
+            "<small>".repeat(1000),
+            "</small>".repeat(1000)
+        );
+        let [tiny, normal] = heights(&html)[..] else {
+            panic!("expected two words");
+        };
+        assert!(tiny >= MIN_FONT_SIZE);
+        assert!((normal - 16.).abs() < 0.01);
+
+        let [huge] = heights(&format!("{}huge", "<big>".repeat(100)))[..] else {
+            panic!("expected one word");
+        };
+        assert!(huge <= MAX_FONT_SIZE);
+        let [unbalanced] = heights(&format!("{}x", "</small>".repeat(100)))[..] else {
+            panic!("expected one word");
+        };
+        assert!((unbalanced - 16.).abs() < 0.01);
+    }
+
+    #[test]
+    fn huge_table_spans_are_clamped() {
+        let words = layout_words(
+            "<table><tr><td colspan=18446744073709551615 rowspan=99999999999>a\
+             <td colspan=2000000000>b</table>",
+        );
+        assert_eq!(words, ["a", "b"]);
+    }
+
+    #[test]
+    fn right_to_left_lines_are_drawn_reversed_but_keep_their_text() {
+        // Four two-letter words and three spaces, against the right edge
+        let start = 800. - HSTEP - (4. * 16. + 3. * 8.);
+        assert_eq!(
+            layout_html("<p dir=rtl>אב גד ab cd</p>", 800.),
+            [
+                ("אב".to_string(), start + 72., FIRST_LINE),
+                ("גד".to_string(), start + 48., FIRST_LINE),
+                ("ab".to_string(), start, FIRST_LINE),
+                ("cd".to_string(), start + 24., FIRST_LINE),
+            ]
+        );
+    }
+
+    #[test]
+    fn list_markers_hang_in_the_indent() {
+        let start = HSTEP + LIST_INDENT;
+        assert_eq!(
+            layout_html("<ul><li>ab</li></ul>", 800.),
+            [
+                ("•".to_string(), start - 8. - 8., FIRST_LINE),
+                ("ab".to_string(), start, FIRST_LINE),
+            ]
+        );
+    }
+}
@@ -0,0 +1,20 @@
+/*!
+ * The shelves browser engine: fetching, parsing, formatting and laying out pages. The `shelves`
+ * binary is an egui frontend over this.
+ */
+
+pub mod bidi;
+pub mod client;
+pub mod images;
+pub mod layout;
+pub mod list;
+pub mod loader;
+mod page;
+pub mod parser;
+pub mod shaping;
+pub mod style;
+pub mod table;
+pub mod url;
+
+pub use loader::LoadError;
+pub use page::Page;
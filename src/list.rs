@@ -0,0 +1,126 @@
+/**
+ * The kinds of list item marker, named after their `list-style-type` values
+ */
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ListStyle {
+    Disc,
+    Circle,
+    Square,
+    Decimal,
+    LowerAlpha,
+    UpperAlpha,
+    LowerRoman,
+    UpperRoman,
+    None,
+}
+
+impl ListStyle {
+    /**
+     * The marker for an unordered list nested `depth` lists deep, cycling like other browsers do
+     */
+    pub fn unordered(depth: usize) -> ListStyle {
+        match depth % 3 {
+            0 => ListStyle::Disc,
+            1 => ListStyle::Circle,
+            _ => ListStyle::Square,
+        }
+    }
+
+    pub fn from_css(value: &str) -> Option<ListStyle> {
+        Some(match value.trim().to_ascii_lowercase().as_str() {
+            "disc" => ListStyle::Disc,
+            "circle" => ListStyle::Circle,
+            "square" => ListStyle::Square,
+            "decimal" => ListStyle::Decimal,
+            "lower-alpha" | "lower-latin" => ListStyle::LowerAlpha,
+            "upper-alpha" | "upper-latin" => ListStyle::UpperAlpha,
+            "lower-roman" => ListStyle::LowerRoman,
+            "upper-roman" => ListStyle::UpperRoman,
+            "none" => ListStyle::None,
+            _ => return None,
+        })
+    }
+
+    /**
+     * Parse the legacy `type` attribute of <ol> and <ul>, which is case sensitive for <ol>
+     */
+    pub fn from_type_attribute(value: &str) -> Option<ListStyle> {
+        Some(match value.trim() {
+            "1" => ListStyle::Decimal,
+            "a" => ListStyle::LowerAlpha,
+            "A" => ListStyle::UpperAlpha,
+            "i" => ListStyle::LowerRoman,
+            "I" => ListStyle::UpperRoman,
+            other => return ListStyle::from_css(other),
+        })
+    }
+
+    /**
+     * The text drawn before the list item with the given number, if any
+     */
+    pub fn marker(&self, number: i64) -> Option<String> {
+        let text = match self {
+            ListStyle::Disc => "•".to_string(),
+            ListStyle::Circle => "◦".to_string(),
+            ListStyle::Square => "▪".to_string(),
+            ListStyle::Decimal => format!("{number}."),
+            ListStyle::LowerAlpha => format!("{}.", alphabetic(number)?),
+            ListStyle::UpperAlpha => format!("{}.", alphabetic(number)?.to_ascii_uppercase()),
+            ListStyle::LowerRoman => format!("{}.", roman(number)?),
+            ListStyle::UpperRoman => format!("{}.", roman(number)?.to_ascii_uppercase()),
+            ListStyle::None => return None,
+        };
+        Some(text)
+    }
+}
+
+/**
+ * a, b, ..., z, aa, ab, ... Numbers below one have no letter, so they fall back to decimal.
+ */
+fn alphabetic(number: i64) -> Option<String> {
+    if number < 1 {
+        return Some(number.to_string());
+    }
+    let mut number = number;
+    let mut letters = Vec::new();
+    while number > 0 {
+        number -= 1;
+        letters.push(b'a' + (number % 26) as u8);
+        number /= 26;
+    }
+    letters.reverse();
+    String::from_utf8(letters).ok()
+}
+
+/**
+ * Lowercase roman numerals, falling back to decimal outside the range they can write
+ */
+fn roman(number: i64) -> Option<String> {
+    if !(1..4000).contains(&number) {
+        return Some(number.to_string());
+    }
+    const NUMERALS: [(i64, &str); 13] = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    let mut number = number;
+    let mut text = String::new();
+    for (value, numeral) in NUMERALS {
+        while number >= value {
+            text.push_str(numeral);
+            number -= value;
+        }
+    }
+    Some(text)
+}
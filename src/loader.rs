@@ -0,0 +1,244 @@
+use std::{
+    io, mem,
+    str::{self, Utf8Error},
+    sync::{
+        mpsc::{self, Receiver, RecvError, Sender, TryRecvError},
+        Arc,
+    },
+    thread,
+};
+
+use thiserror::Error;
+
+use crate::{
+    client::{CancelHandle, Client, RequestError},
+    layout::{FormatToken, Formatter},
+    parser::{Lexer, Token},
+    url::Url,
+};
+
+/**
+ * How many bytes of the body to lex and format before handing the results over
+ */
+const CHUNK_SIZE: usize = 4096;
+
+pub enum LoadEvent {
+    /// The server responded, so the old page can be replaced
+    Committed,
+    Tokens(Vec<FormatToken>),
+    Title(String),
+    Finished,
+    Failed(LoadError),
+}
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error(transparent)]
+    Request(#[from] RequestError),
+    #[error("page isn't valid UTF-8")]
+    NotUtf8(#[source] Utf8Error),
+    #[error("the page loader crashed")]
+    Crashed,
+}
+
+/**
+ * Fetches, parses and formats a page on a worker thread, reporting back as it goes. Dropping the
+ * loader cancels the load.
+ */
+pub struct Loader {
+    events: Receiver<LoadEvent>,
+    cancel: CancelHandle,
+    /// Whether the worker has reported that it finished or failed
+    done: bool,
+}
+
+impl Loader {
+    /**
+     * Start loading a page. `wake` is called from the worker thread after each event, so a UI can
+     * redraw.
+     */
+    pub fn start(client: Arc<Client>, url: Url, wake: impl Fn() + Send + 'static) -> Loader {
+        let (sender, events) = mpsc::channel();
+        let cancel = CancelHandle::default();
+
+        let worker_cancel = cancel.clone();
+        thread::spawn(move || {
+            let worker = Worker {
+                wake: Box::new(wake),
+                sender,
+                cancel: worker_cancel,
+            };
+            if let Err(err) = worker.load(&client, &url) {
+                worker.send(LoadEvent::Failed(err));
+            }
+        });
+
+        Loader {
+            events,
+            cancel,
+            done: false,
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /**
+     * Everything the worker has reported since the last poll
+     */
+    pub fn poll(&mut self) -> Vec<LoadEvent> {
+        let mut events = Vec::new();
+        while !self.done {
+            match self.events.try_recv() {
+                Ok(event) => events.push(self.received(event)),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => events.extend(self.hung_up()),
+            }
+        }
+        events
+    }
+
+    /**
+     * Wait for the worker's next report. None once it has finished or failed.
+     */
+    pub fn wait(&mut self) -> Option<LoadEvent> {
+        if self.done {
+            return None;
+        }
+        match self.events.recv() {
+            Ok(event) => Some(self.received(event)),
+            Err(RecvError) => self.hung_up(),
+        }
+    }
+
+    fn received(&mut self, event: LoadEvent) -> LoadEvent {
+        self.done = matches!(event, LoadEvent::Finished | LoadEvent::Failed(_));
+        event
+    }
+
+    /**
+     * The worker hung up without saying how the load ended, so unless it was cancelled, it
+     * panicked
+     */
+    fn hung_up(&mut self) -> Option<LoadEvent> {
+        self.done = true;
+        (!self.cancel.is_cancelled()).then_some(LoadEvent::Failed(LoadError::Crashed))
+    }
+}
+
+impl Drop for Loader {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+struct Worker {
+    wake: Box<dyn Fn() + Send>,
+    sender: Sender<LoadEvent>,
+    cancel: CancelHandle,
+}
+
+impl Worker {
+    fn load(&self, client: &Client, url: &Url) -> Result<(), LoadError> {
+        let mut parse = Parse::default();
+        let mut committed = false;
+        let mut not_utf8 = None;
+        let result = client.request_streaming(url, &self.cancel, &mut |bytes| {
+            if !committed {
+                self.send(LoadEvent::Committed);
+                committed = true;
+            }
+            let mut pending = mem::take(&mut parse.pending);
+            pending.extend_from_slice(bytes);
+            let decoded = match str::from_utf8(&pending) {
+                Ok(text) => text.len(),
+                // A character split between chunks is finished by the next one
+                Err(err) if err.error_len().is_none() => err.valid_up_to(),
+                Err(err) => {
+                    not_utf8 = Some(err);
+                    return Err(io::ErrorKind::InvalidData.into());
+                }
+            };
+            parse.pending = pending.split_off(decoded);
+            self.feed(&mut parse, str::from_utf8(&pending).unwrap());
+            Ok(())
+        });
+        if self.is_cancelled() {
+            return Ok(());
+        }
+        if let Some(err) = not_utf8 {
+            return Err(LoadError::NotUtf8(err));
+        }
+        result?;
+        str::from_utf8(&parse.pending).map_err(LoadError::NotUtf8)?;
+        if !committed {
+            self.send(LoadEvent::Committed);
+        }
+        // The last word is only known to have ended once the body has
+        let tokens = parse.lexer.finish();
+        self.format(&mut parse, &tokens);
+
+        self.send(LoadEvent::Finished);
+        Ok(())
+    }
+
+    /**
+     * Lex and format newly arrived text, a few kilobytes at a time so the page fills in smoothly
+     */
+    fn feed(&self, parse: &mut Parse, text: &str) {
+        let mut rest = text;
+        while !rest.is_empty() {
+            if self.is_cancelled() {
+                return;
+            }
+            let mut end = CHUNK_SIZE.min(rest.len());
+            while !rest.is_char_boundary(end) {
+                end += 1;
+            }
+            let (chunk, remaining) = rest.split_at(end);
+            rest = remaining;
+
+            let tokens = parse.lexer.feed(chunk);
+            self.format(parse, &tokens);
+        }
+    }
+
+    /**
+     * Format lexed tokens and report what they add to the page
+     */
+    fn format(&self, parse: &mut Parse, tokens: &[Token]) {
+        let tokens = parse.formatter.format(tokens);
+        if !tokens.is_empty() {
+            self.send(LoadEvent::Tokens(tokens));
+        }
+        if parse.title.is_none() {
+            parse.title = parse.formatter.title();
+            if let Some(title) = &parse.title {
+                self.send(LoadEvent::Title(title.clone()));
+            }
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    fn send(&self, event: LoadEvent) {
+        // The loader hanging up just means nobody is waiting on this load anymore
+        let _ = self.sender.send(event);
+        (self.wake)();
+    }
+}
+
+/**
+ * How far through the body the worker is
+ */
+#[derive(Default)]
+struct Parse {
+    lexer: Lexer,
+    formatter: Formatter,
+    title: Option<String>,
+    /// The end of the last chunk, if it stopped partway through a character
+    pending: Vec<u8>,
+}
@@ -1,25 +1,83 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-mod client;
-mod layout;
-mod parser;
-mod url;
-
-use eframe::{
-    egui::{self, Event, MouseWheelUnit, Vec2},
-    epaint::Color32,
+mod find;
+mod headless;
+mod history;
+mod raster;
+mod selection;
+mod tab;
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use anyhow::{anyhow, bail};
+use eframe::egui::{
+    self, Color32, CursorIcon, Event, Key, Modifiers, MouseWheelUnit, PointerButton, Rect, Sense,
+    Vec2,
 };
 
-use client::Client;
-use layout::{layout, DisplayListItem, FormatToken, VSTEP};
-use url::Url;
+use find::Find;
+use headless::DumpFormat;
+use selection::{hit_test, Selection};
+use shelves::{
+    client::Client,
+    layout::VSTEP,
+    url::{Url, UrlError},
+};
+use tab::{LoadFailure, Navigation, Tab};
+
+/**
+ * The command line: `shelves [--headless] [--screenshot PATH] [--width N] [--format text|json] [URL]`
+ */
+struct Args {
+    url: Option<String>,
+    /// Print the page's layout instead of opening a window
+    headless: bool,
+    /// Save a PNG of the page instead of opening a window
+    screenshot: Option<PathBuf>,
+    width: f32,
+    format: DumpFormat,
+}
 
-use crate::layout::format_tokens;
+impl Args {
+    fn parse() -> anyhow::Result<Args> {
+        let mut parsed = Args {
+            url: None,
+            headless: false,
+            screenshot: None,
+            width: WIDTH,
+            format: DumpFormat::Text,
+        };
+        let mut args = std::env::args().skip(1); // discard binary name
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| args.next().ok_or_else(|| anyhow!("{flag} needs a value"));
+            match arg.as_str() {
+                "--headless" => parsed.headless = true,
+                "--screenshot" => parsed.screenshot = Some(value("--screenshot")?.into()),
+                "--width" => {
+                    let width = value("--width")?;
+                    parsed.width = width
+                        .parse()
+                        .ok()
+                        .filter(|width: &f32| *width > 0.)
+                        .ok_or_else(|| anyhow!("invalid width {width}"))?;
+                }
+                "--format" => {
+                    let format = value("--format")?;
+                    parsed.format = DumpFormat::parse(&format)
+                        .ok_or_else(|| anyhow!("unknown format {format}, expected text or json"))?;
+                }
+                flag if flag.starts_with("--") => bail!("unknown option {flag}"),
+                _ if parsed.url.is_some() => bail!("more than one URL given"),
+                _ => parsed.url = Some(arg),
+            }
+        }
+        Ok(parsed)
+    }
+}
 
 fn main() -> anyhow::Result<()> {
-    let mut args = std::env::args();
-    args.next().unwrap(); // discard binary name
-    let url = match args.next() {
+    let args = Args::parse()?;
+    let url = match args.url {
         Some(url) => url,
         None => {
             let dir = std::env::current_dir()?;
@@ -27,27 +85,44 @@ fn main() -> anyhow::Result<()> {
         }
     };
 
-    let input = Url::new(url)?;
-    let mut client = Client::new();
-    let resp = client.request(&input)?;
+    let input = Url::from_user_input(&url);
+    if args.headless || args.screenshot.is_some() {
+        let rendered = headless::render(&input?, args.width, HEIGHT)?;
+        if args.headless {
+            print!("{}", headless::dump(&rendered, args.format));
+        }
+        if let Some(path) = &args.screenshot {
+            headless::screenshot(&rendered, args.width)
+                .save_with_format(path, image::ImageFormat::Png)?;
+        }
+        return Ok(());
+    }
 
-    eprintln!("{:?}", resp.headers);
-    let contents = parser::lex(resp.body.as_str()?);
+    let mut tab = Tab::new();
+    match input {
+        Ok(input) => tab.start_navigation(Navigation::Load(input)),
+        Err(err) => tab.fail(LoadFailure::new(url, &err.into(), None)),
+    }
 
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([WIDTH, HEIGHT]),
         ..Default::default()
     };
 
-    let format_tokens = format_tokens(&contents[..]);
-
     eframe::run_native(
-        "My egui App",
+        APP_NAME,
         options,
         Box::new(|_cc| {
             Ok(Box::new(Browser {
-                format_tokens,
-                scroll: Vec2::ZERO,
+                client: Arc::new(Client::new()),
+                tabs: vec![tab],
+                active: 0,
+                focus_address: false,
+                focus_find: false,
+                smooth_scrolling: false,
+                zoom_levels: HashMap::new(),
+                copy_requested: false,
+                window_title: String::new(),
             }))
         }),
     )
@@ -56,68 +131,635 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+enum ZoomChange {
+    In,
+    Out,
+    Reset,
+    By(f32),
+}
+
 struct Browser {
-    format_tokens: Vec<FormatToken>,
-    scroll: Vec2,
+    client: Arc<Client>,
+    tabs: Vec<Tab>,
+    active: usize,
+    focus_address: bool,
+    focus_find: bool,
+    smooth_scrolling: bool,
+    zoom_levels: HashMap<String, f32>,
+    copy_requested: bool,
+    window_title: String,
 }
 
-const WIDTH: f32 = 800.;
-const HEIGHT: f32 = 600.;
+impl Browser {
+    fn tab(&mut self) -> &mut Tab {
+        &mut self.tabs[self.active]
+    }
 
-impl eframe::App for Browser {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            let draw_list = layout(ui, &self.format_tokens[..]);
-            for display in draw_list.iter() {
-                ui.painter().galley(
-                    display.pos + self.scroll,
-                    display.galley.clone(),
-                    Default::default(),
-                );
+    fn new_tab(&mut self) {
+        self.tabs.push(Tab::new());
+        self.active = self.tabs.len() - 1;
+        self.focus_address = true;
+    }
+
+    /**
+     * Open a link in a new tab next to the current one without switching to it
+     */
+    fn open_in_background(&mut self, url: Url) {
+        let mut tab = Tab::new();
+        tab.address = url.to_string();
+        tab.start_navigation(Navigation::Load(url));
+        self.tabs.insert(self.active + 1, tab);
+    }
+
+    fn close_tab(&mut self, ctx: &egui::Context, index: usize) {
+        if self.tabs.len() == 1 {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            return;
+        }
+        self.tabs.remove(index);
+        if self.active > index || self.active == self.tabs.len() {
+            self.active -= 1;
+        }
+    }
+
+    fn cycle_tabs(&mut self, backwards: bool) {
+        let count = self.tabs.len();
+        self.active = if backwards {
+            (self.active + count - 1) % count
+        } else {
+            (self.active + 1) % count
+        };
+    }
+
+    fn tab_strip(&mut self, ui: &mut egui::Ui) {
+        let mut close = None;
+        ui.horizontal(|ui| {
+            for (index, tab) in self.tabs.iter().enumerate() {
+                let mut title = tab.title();
+                if title.chars().count() > 24 {
+                    title = title.chars().take(23).chain(Some('…')).collect();
+                }
+                let label = ui.selectable_label(index == self.active, title);
+                if label.clicked() {
+                    self.active = index;
+                }
+                if label.middle_clicked() || ui.small_button("×").clicked() {
+                    close = Some(index);
+                }
+            }
+            if ui.button("+").on_hover_text("New tab (Ctrl+T)").clicked() {
+                self.new_tab();
             }
         });
+        if let Some(index) = close {
+            self.close_tab(ui.ctx(), index);
+        }
     }
 
-    fn raw_input_hook(&mut self, _ctx: &egui::Context, raw_input: &mut egui::RawInput) {
-        for event in raw_input.events.drain(..) {
-            match event {
-                Event::MouseWheel {
-                    unit,
-                    delta,
-                    modifiers: _,
-                } => {
-                    let px = match unit {
-                        MouseWheelUnit::Point => delta,
-                        MouseWheelUnit::Line => delta * VSTEP,
-                        MouseWheelUnit::Page => todo!(),
-                    };
-                    self.scroll += px;
+    /**
+     * The zoom level of the current tab, which is shared by every page from the same origin
+     */
+    fn zoom(&self) -> f32 {
+        self.tabs[self.active]
+            .history
+            .current()
+            .and_then(|entry| self.zoom_levels.get(&entry.url.origin()))
+            .copied()
+            .unwrap_or(1.)
+    }
+
+    fn change_zoom(&mut self, change: ZoomChange) {
+        let zoom = self.zoom();
+        let zoom = match change {
+            ZoomChange::In => ZOOM_LEVELS
+                .iter()
+                .copied()
+                .find(|level| *level > zoom + 0.01)
+                .unwrap_or(zoom),
+            ZoomChange::Out => ZOOM_LEVELS
+                .iter()
+                .rev()
+                .copied()
+                .find(|level| *level < zoom - 0.01)
+                .unwrap_or(zoom),
+            ZoomChange::Reset => 1.,
+            ZoomChange::By(factor) => zoom * factor,
+        }
+        .clamp(MIN_ZOOM, MAX_ZOOM);
+
+        let Some(entry) = self.tabs[self.active].history.current() else {
+            return;
+        };
+        let origin = entry.url.origin();
+        if zoom == 1. {
+            self.zoom_levels.remove(&origin);
+        } else {
+            self.zoom_levels.insert(origin, zoom);
+        }
+    }
+
+    fn chrome(&mut self, ui: &mut egui::Ui) {
+        let focus_address = std::mem::take(&mut self.focus_address);
+        let zoom = self.zoom();
+        let mut zoom_change = None;
+        let smooth_scrolling = &mut self.smooth_scrolling;
+        let tab = &mut self.tabs[self.active];
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(tab.history.can_go_back(), egui::Button::new("◀"))
+                .on_hover_text("Back (Alt+Left)")
+                .clicked()
+            {
+                tab.start_navigation(Navigation::Back);
+            }
+            if ui
+                .add_enabled(tab.history.can_go_forward(), egui::Button::new("▶"))
+                .on_hover_text("Forward (Alt+Right)")
+                .clicked()
+            {
+                tab.start_navigation(Navigation::Forward);
+            }
+            if tab.is_loading() {
+                if ui.button("✕").on_hover_text("Stop (Esc)").clicked() {
+                    tab.stop();
                 }
-                Event::Key { key, pressed, .. } => {
-                    if !pressed {
-                        continue;
+            } else if ui.button("⟳").on_hover_text("Reload (F5)").clicked() {
+                tab.start_navigation(Navigation::Reload);
+            }
+
+            ui.menu_button("☰", |ui| {
+                ui.checkbox(smooth_scrolling, "Smooth scrolling");
+                ui.horizontal(|ui| {
+                    ui.label("Zoom");
+                    if ui.button("−").on_hover_text("Zoom out (Ctrl+-)").clicked() {
+                        zoom_change = Some(ZoomChange::Out);
                     }
-                    match key {
-                        egui::Key::ArrowDown => self.scroll.y -= VSTEP,
-                        egui::Key::ArrowUp => self.scroll.y += VSTEP,
-                        _ => {}
+                    ui.label(format!("{:.0}%", zoom * 100.));
+                    if ui.button("+").on_hover_text("Zoom in (Ctrl++)").clicked() {
+                        zoom_change = Some(ZoomChange::In);
+                    }
+                    if ui
+                        .button("Reset")
+                        .on_hover_text("Reset zoom (Ctrl+0)")
+                        .clicked()
+                    {
+                        zoom_change = Some(ZoomChange::Reset);
+                    }
+                });
+            });
+
+            let indicator_width = if tab.is_loading() { 24. } else { 0. };
+            let address_bar = ui.add(
+                egui::TextEdit::singleline(&mut tab.address)
+                    .desired_width(ui.available_width() - indicator_width),
+            );
+            if focus_address {
+                address_bar.request_focus();
+            }
+            if address_bar.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                match Url::from_user_input(&tab.address) {
+                    Ok(url) => tab.start_navigation(Navigation::Load(url)),
+                    Err(err) => {
+                        let address = tab.address.clone();
+                        tab.fail(LoadFailure::new(address, &err.into(), None));
                     }
                 }
-                Event::Copy
-                | Event::Cut
-                | Event::Paste(_)
-                | Event::Text(_)
-                | Event::PointerMoved(_)
-                | Event::MouseMoved(_)
-                | Event::PointerButton { .. }
-                | Event::PointerGone
-                | Event::Zoom(_)
-                | Event::Ime(_)
-                | Event::Touch { .. }
-                | Event::WindowFocused(_)
-                | Event::AccessKitActionRequest(_)
-                | Event::Screenshot { .. } => {}
+            }
+
+            if tab.is_loading() {
+                ui.spinner();
+            }
+        });
+        if let Some(change) = zoom_change {
+            self.change_zoom(change);
+        }
+    }
+
+    fn find_bar(&mut self, ui: &mut egui::Ui) {
+        let focus_find = std::mem::take(&mut self.focus_find);
+        let tab = &mut self.tabs[self.active];
+        let Some(find) = &mut tab.find else {
+            return;
+        };
+        let mut step = None;
+        let mut close = false;
+        ui.horizontal(|ui| {
+            ui.label("Find:");
+            let field = ui.add(egui::TextEdit::singleline(&mut find.query).desired_width(240.));
+            if focus_find {
+                field.request_focus();
+            }
+            if field.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                step = Some(ui.input(|i| i.modifiers.shift));
+                field.request_focus();
+            }
+            if ui
+                .button("▲")
+                .on_hover_text("Previous (Shift+Enter)")
+                .clicked()
+            {
+                step = Some(true);
+            }
+            if ui.button("▼").on_hover_text("Next (Enter)").clicked() {
+                step = Some(false);
+            }
+            match find.matches().len() {
+                0 if find.query.trim().is_empty() => {}
+                0 => {
+                    ui.label("No matches");
+                }
+                count => {
+                    ui.label(format!("{} of {count}", find.current_index() + 1));
+                }
+            }
+            if ui.button("✕").on_hover_text("Close (Esc)").clicked() {
+                close = true;
+            }
+        });
+        if let Some(backwards) = step {
+            tab.find_step(backwards);
+        }
+        if close {
+            tab.find = None;
+        }
+    }
+
+    fn page(&mut self, ui: &mut egui::Ui) {
+        if self.tab().failure.is_some() {
+            error_page(ui, self.tab());
+            return;
+        }
+        let response = ui.interact(ui.max_rect(), ui.id().with("page"), Sense::click_and_drag());
+        let origin = ui.min_rect().min.to_vec2();
+        let dt = ui.input(|i| i.stable_dt);
+        let zoom = self.zoom();
+        let tab = &mut self.tabs[self.active];
+        tab.update_layout(ui, zoom);
+        let (scroll, animating) = tab.animate_scroll(self.smooth_scrolling, dt);
+        if animating {
+            ui.ctx().request_repaint();
+        }
+
+        // Pointer positions relative to the top of the document
+        let to_page = |pos: egui::Pos2| pos - origin - scroll;
+        let pointer = response.hover_pos().map(to_page);
+        if let Some(press) = ui.input(|i| i.pointer.press_origin()).map(to_page) {
+            if response.drag_started_by(PointerButton::Primary) {
+                tab.selection = hit_test(tab.display_list(), press).map(Selection::collapsed);
+            }
+        }
+        if response.dragged_by(PointerButton::Primary) {
+            let focus = response
+                .interact_pointer_pos()
+                .and_then(|pos| hit_test(tab.display_list(), to_page(pos)));
+            if let (Some(selection), Some(focus)) = (&mut tab.selection, focus) {
+                selection.focus = focus;
             }
         }
+        if response.double_clicked() {
+            tab.selection = pointer
+                .and_then(|pos| hit_test(tab.display_list(), pos))
+                .map(|position| Selection::word(tab.display_list(), position.item));
+        } else if response.clicked() {
+            tab.selection = None;
+        }
+
+        if let Some(find) = &tab.find {
+            for (index, found) in find.matches().iter().enumerate() {
+                let color = if index == find.current_index() {
+                    FIND_CURRENT_HIGHLIGHT
+                } else {
+                    FIND_HIGHLIGHT
+                };
+                for rect in found.highlight_rects(tab.display_list()) {
+                    ui.painter()
+                        .rect_filled(rect.translate(origin + scroll), 0., color);
+                }
+            }
+        }
+
+        if let Some(selection) = &tab.selection {
+            for rect in selection.highlight_rects(tab.display_list()) {
+                ui.painter().rect_filled(
+                    rect.translate(origin + scroll),
+                    0.,
+                    ui.visuals().selection.bg_fill,
+                );
+            }
+        }
+
+        let mut hovered_link = None;
+        for display in tab.display_list().iter() {
+            let rect = display.rect().translate(origin + scroll);
+            display.paint(ui.painter(), origin + scroll);
+
+            if response
+                .hover_pos()
+                .is_some_and(|pointer| rect.contains(pointer))
+            {
+                ui.ctx().set_cursor_icon(CursorIcon::Text);
+                hovered_link = display.link.clone();
+            }
+        }
+
+        if let Some(ring) = tab.focus_ring() {
+            ui.painter().rect_stroke(
+                ring.translate(origin + scroll).expand(2.),
+                2.,
+                ui.visuals().selection.stroke,
+            );
+        }
+
+        scrollbar(ui, tab, scroll);
+
+        let Some(href) = hovered_link else {
+            return;
+        };
+        ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
+        let new_tab =
+            response.middle_clicked() || (response.clicked() && ui.input(|i| i.modifiers.command));
+        if new_tab || response.clicked() {
+            self.follow_link(&href, new_tab);
+        }
+    }
+
+    fn follow_link(&mut self, href: &str, new_tab: bool) {
+        let base = self.tabs[self.active]
+            .history
+            .current()
+            .map(|entry| entry.url.clone());
+        let url = match &base {
+            Some(base) => base.resolve(href),
+            None => Url::new(href.to_string()),
+        };
+        match url {
+            Ok(url) if new_tab => self.open_in_background(url),
+            Ok(url) => self.tab().start_navigation(Navigation::Load(url)),
+            // Links like mailto: are for other programs, so the page stays as it is
+            Err(UrlError::UnknownScheme(_)) => {}
+            Err(err) => {
+                let err = anyhow::Error::from(err).context("invalid link");
+                self.tab()
+                    .fail(LoadFailure::new(href.to_string(), &err, None));
+            }
+        }
+    }
+
+    /**
+     * React to input meant for the page. Returns true if the event was used up and shouldn't also
+     * reach egui, like Tab which would otherwise move focus around the browser chrome.
+     */
+    fn handle_event(&mut self, ctx: &egui::Context, event: &Event) -> bool {
+        match event {
+            Event::MouseWheel {
+                unit: _,
+                delta,
+                modifiers,
+            } if modifiers.command => {
+                let change = if delta.y > 0. {
+                    ZoomChange::In
+                } else {
+                    ZoomChange::Out
+                };
+                self.change_zoom(change);
+                return true;
+            }
+            Event::MouseWheel {
+                unit,
+                delta,
+                modifiers: _,
+            } => {
+                let tab = self.tab();
+                match unit {
+                    MouseWheelUnit::Point => tab.scroll_down(-delta.y),
+                    MouseWheelUnit::Line => tab.scroll_down(-delta.y * VSTEP),
+                    MouseWheelUnit::Page => tab.scroll_page(-delta.y),
+                }
+            }
+            Event::Key {
+                key,
+                pressed,
+                modifiers,
+                ..
+            } => {
+                if !pressed {
+                    return false;
+                }
+                match (*key, *modifiers) {
+                    (Key::F5, _) => self.tab().start_navigation(Navigation::Reload),
+                    (Key::R, m) if m.command_only() => {
+                        self.tab().start_navigation(Navigation::Reload)
+                    }
+                    (Key::T, m) if m.command_only() => self.new_tab(),
+                    (Key::Plus | Key::Equals, m) if m.command => {
+                        self.change_zoom(ZoomChange::In);
+                        return true;
+                    }
+                    (Key::Minus, m) if m.command => {
+                        self.change_zoom(ZoomChange::Out);
+                        return true;
+                    }
+                    (Key::Num0, m) if m.command => {
+                        self.change_zoom(ZoomChange::Reset);
+                        return true;
+                    }
+                    (Key::W, m) if m.command_only() => self.close_tab(ctx, self.active),
+                    (Key::Tab, m) if m.command => self.cycle_tabs(m.shift),
+                    (Key::Escape, _) if self.tab().is_loading() => self.tab().stop(),
+                    (Key::Escape, _) if self.tab().find.is_some() => self.tab().find = None,
+                    (Key::F, m) if m.command_only() => {
+                        let tab = self.tab();
+                        tab.find.get_or_insert_with(Find::new);
+                        self.focus_find = true;
+                        return true;
+                    }
+                    // Leave the remaining keys to the address bar while it's focused
+                    _ if ctx.wants_keyboard_input() => {}
+                    // Alt+arrows move by a word while typing, so only navigate outside text fields
+                    (Key::ArrowLeft, Modifiers::ALT) => {
+                        self.tab().start_navigation(Navigation::Back)
+                    }
+                    (Key::ArrowRight, Modifiers::ALT) => {
+                        self.tab().start_navigation(Navigation::Forward)
+                    }
+                    (Key::A, m) if m.command_only() => {
+                        let tab = self.tab();
+                        tab.selection = Selection::all(tab.display_list());
+                        return true;
+                    }
+                    (Key::Tab, m) => {
+                        self.tab().focus_next(m.shift);
+                        return true;
+                    }
+                    (Key::Enter, _) => {
+                        // Inputs and buttons can take focus but don't do anything yet without forms
+                        if let Some(href) = self.tab().focused_link().map(str::to_string) {
+                            self.follow_link(&href, false);
+                            return true;
+                        }
+                    }
+                    (Key::ArrowDown, _) => self.tab().scroll_down(VSTEP),
+                    (Key::ArrowUp, _) => self.tab().scroll_down(-VSTEP),
+                    (Key::PageDown, _) => self.tab().scroll_page(1.),
+                    (Key::PageUp, _) => self.tab().scroll_page(-1.),
+                    (Key::Space, m) => self.tab().scroll_page(if m.shift { -1. } else { 1. }),
+                    (Key::Home, _) => self.tab().scroll_to(0.),
+                    (Key::End, _) => {
+                        let tab = self.tab();
+                        tab.scroll_to(tab.max_scroll());
+                    }
+                    _ => {}
+                }
+            }
+            Event::Copy if !ctx.wants_keyboard_input() => self.copy_requested = true,
+            Event::Zoom(factor) => {
+                self.change_zoom(ZoomChange::By(*factor));
+                return true;
+            }
+            Event::Copy
+            | Event::Cut
+            | Event::Paste(_)
+            | Event::Text(_)
+            | Event::PointerMoved(_)
+            | Event::MouseMoved(_)
+            | Event::PointerButton { .. }
+            | Event::PointerGone
+            | Event::Ime(_)
+            | Event::Touch { .. }
+            | Event::WindowFocused(_)
+            | Event::AccessKitActionRequest(_)
+            | Event::Screenshot { .. } => {}
+        }
+        false
+    }
+
+    fn update_window_title(&mut self, ctx: &egui::Context) {
+        let tab = &self.tabs[self.active];
+        let title = if tab.is_loading() {
+            format!("Loading… - {APP_NAME}")
+        } else {
+            format!("{} - {APP_NAME}", tab.title())
+        };
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+    }
+}
+
+/**
+ * Explain why the tab's page couldn't be shown, with a button to try again if that could help
+ */
+fn error_page(ui: &mut egui::Ui, tab: &mut Tab) {
+    let Some(failure) = &tab.failure else {
+        return;
+    };
+    let mut retry = false;
+    egui::Frame::none()
+        .inner_margin(ERROR_PAGE_MARGIN)
+        .show(ui, |ui| {
+            ui.heading("Couldn't load this page");
+            ui.add_space(ERROR_PAGE_SPACING);
+            ui.label(egui::RichText::new(&failure.address).monospace());
+            ui.add_space(ERROR_PAGE_SPACING);
+            for (depth, cause) in failure.causes.iter().enumerate() {
+                if depth == 0 {
+                    ui.label(cause);
+                } else {
+                    ui.label(format!("caused by: {cause}"));
+                }
+            }
+            if failure.retry.is_some() {
+                ui.add_space(ERROR_PAGE_SPACING);
+                retry = ui.button("Try again").clicked();
+            }
+        });
+    if retry {
+        tab.retry();
+    }
+}
+
+/**
+ * Draw a scrollbar along the right edge of the page which can be dragged or clicked to jump
+ */
+fn scrollbar(ui: &egui::Ui, tab: &mut Tab, scroll: Vec2) {
+    let document_height = tab.document_height();
+    let viewport_height = tab.viewport_height();
+    if document_height <= viewport_height {
+        return;
+    }
+
+    let page = ui.max_rect();
+    let track = Rect::from_min_max(
+        egui::pos2(page.right() - SCROLLBAR_WIDTH, page.top()),
+        page.right_bottom(),
+    );
+    let thumb_height = (track.height() * viewport_height / document_height).max(SCROLLBAR_WIDTH);
+    let travel = track.height() - thumb_height;
+    let progress = (-scroll.y / tab.max_scroll()).clamp(0., 1.);
+    let thumb = Rect::from_min_size(
+        egui::pos2(track.left(), track.top() + travel * progress),
+        egui::vec2(SCROLLBAR_WIDTH, thumb_height),
+    );
+
+    let response = ui.interact(track, ui.id().with("scrollbar"), Sense::click_and_drag());
+    if response.dragged() {
+        tab.scroll_down(response.drag_delta().y * tab.max_scroll() / travel);
+    } else if let Some(pointer) = response
+        .interact_pointer_pos()
+        .filter(|_| response.clicked())
+    {
+        let progress = (pointer.y - track.top() - thumb_height / 2.) / travel;
+        tab.scroll_to(progress.clamp(0., 1.) * tab.max_scroll());
+    }
+
+    let visuals = ui.style().interact(&response);
+    ui.painter()
+        .rect_filled(track, 0., ui.visuals().extreme_bg_color);
+    ui.painter()
+        .rect_filled(thumb, visuals.rounding, visuals.bg_fill);
+}
+
+const ZOOM_LEVELS: [f32; 13] = [
+    0.5, 0.67, 0.75, 0.8, 0.9, 1., 1.1, 1.25, 1.5, 1.75, 2., 2.5, 3.,
+];
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 5.;
+const SCROLLBAR_WIDTH: f32 = 10.;
+const ERROR_PAGE_MARGIN: f32 = 24.;
+const ERROR_PAGE_SPACING: f32 = 8.;
+const FIND_HIGHLIGHT: Color32 = Color32::from_rgba_premultiplied(90, 80, 0, 90);
+const FIND_CURRENT_HIGHLIGHT: Color32 = Color32::from_rgba_premultiplied(200, 110, 0, 200);
+const APP_NAME: &str = "shelves";
+const WIDTH: f32 = 800.;
+const HEIGHT: f32 = 600.;
+
+impl eframe::App for Browser {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        for tab in self.tabs.iter_mut() {
+            tab.poll(ctx, &self.client);
+        }
+
+        egui::TopBottomPanel::top("tabs").show(ctx, |ui| self.tab_strip(ui));
+        egui::TopBottomPanel::top("chrome").show(ctx, |ui| self.chrome(ui));
+        if self.tab().find.is_some() {
+            egui::TopBottomPanel::top("find").show(ctx, |ui| self.find_bar(ui));
+        }
+        self.update_window_title(ctx);
+
+        egui::CentralPanel::default().show(ctx, |ui| self.page(ui));
+
+        // Clipboard output set while handling raw input would be lost, so copy here instead
+        if std::mem::take(&mut self.copy_requested) {
+            if let Some(text) = self.tab().selected_text() {
+                ctx.copy_text(text);
+            }
+        }
+    }
+
+    fn raw_input_hook(&mut self, ctx: &egui::Context, raw_input: &mut egui::RawInput) {
+        raw_input
+            .events
+            .retain(|event| !self.handle_event(ctx, event));
     }
 }
@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use crate::{
+    client::Client,
+    images::Images,
+    layout::{layout, DisplayListItem, FormatToken, Formatter},
+    loader::{LoadError, LoadEvent, Loader},
+    parser::Lexer,
+    shaping::TextShaper,
+    url::Url,
+};
+
+/**
+ * A document, formatted and ready to be laid out at any width
+ */
+pub struct Page {
+    pub title: Option<String>,
+    pub format_tokens: Vec<FormatToken>,
+}
+
+impl Page {
+    pub fn empty() -> Page {
+        Page {
+            title: None,
+            format_tokens: Vec::new(),
+        }
+    }
+
+    /**
+     * Fetch a page with a fresh client and format it
+     */
+    pub fn load(url: &Url) -> Result<Page, LoadError> {
+        Page::load_with(&Arc::new(Client::new()), url)
+    }
+
+    /**
+     * Fetch a page with an existing client, reusing its connections, and format it. This waits for
+     * a `Loader`, which can be used directly to show the page as it arrives.
+     */
+    pub fn load_with(client: &Arc<Client>, url: &Url) -> Result<Page, LoadError> {
+        let mut loader = Loader::start(client.clone(), url.clone(), || {});
+        let mut page = Page::empty();
+        while let Some(event) = loader.wait() {
+            match event {
+                LoadEvent::Committed | LoadEvent::Finished => {}
+                LoadEvent::Tokens(tokens) => page.format_tokens.extend(tokens),
+                LoadEvent::Title(title) => page.title = Some(title),
+                LoadEvent::Failed(err) => return Err(err),
+            }
+        }
+        Ok(page)
+    }
+
+    pub fn parse(html: &str) -> Page {
+        let mut lexer = Lexer::new();
+        let mut tokens = lexer.feed(html);
+        tokens.extend(lexer.finish());
+        let mut formatter = Formatter::new();
+        let format_tokens = formatter.format(&tokens);
+        Page {
+            title: formatter.title(),
+            format_tokens,
+        }
+    }
+
+    /**
+     * Lay the page out `width` points wide. Images are drawn as placeholders until they're in
+     * `images`.
+     */
+    pub fn layout(
+        &self,
+        shaper: &dyn TextShaper,
+        width: f32,
+        zoom: f32,
+        images: &Images,
+    ) -> Vec<DisplayListItem> {
+        layout(shaper, width, &self.format_tokens, zoom, images)
+    }
+}
@@ -1,69 +1,199 @@
+use std::{collections::HashMap, mem};
+
 use unicode_segmentation::UnicodeSegmentation;
 
+/**
+ * An invisible character marking where a word may be hyphenated, written `&shy;` in HTML
+ */
+pub const SOFT_HYPHEN: char = '\u{AD}';
+
 #[derive(Debug)]
 pub enum Token {
     Tag(String),
     Word(String),
 }
 
-pub fn lex(html: &str) -> Vec<Token> {
-    let mut results = Vec::new();
+/**
+ * Splits HTML into tokens as it arrives, carrying partial tags and words over between chunks
+ */
+pub struct Lexer {
+    state: ParseState,
+    buffer: String,
+    /// The name of the character reference being read, like "amp" in "&amp;"
+    escape: String,
+}
 
-    let mut state = ParseState::Text;
-    let mut buffer = String::new();
+impl Default for Lexer {
+    fn default() -> Lexer {
+        Lexer::new()
+    }
+}
 
-    for grapheme in UnicodeSegmentation::graphemes(html, true) {
-        if state == ParseState::InTag {
-            if grapheme == ">" {
-                results.push(Token::Tag(buffer.clone()));
-                buffer.clear();
-                state = ParseState::Text;
-            } else {
-                buffer.push_str(grapheme);
-            }
-        } else {
-            match grapheme {
-                "<" => {
-                    results.push(Token::Word(buffer.clone()));
-                    buffer.clear();
-                    state = ParseState::InTag;
-                }
-                "&" => {
-                    results.push(Token::Word(buffer.clone()));
-                    buffer.clear();
-                    state = ParseState::EscapeSequence;
-                }
-                ";" if state == ParseState::EscapeSequence => {
-                    results.push(Token::Word(match buffer.as_str() {
-                        "lt" => "<".to_string(),
-                        "gt" => ">".to_string(),
-                        not_recognized => format!("&{buffer};"),
-                    }));
+impl Lexer {
+    pub fn new() -> Lexer {
+        Lexer {
+            state: ParseState::Text,
+            buffer: String::new(),
+            escape: String::new(),
+        }
+    }
+
+    pub fn feed(&mut self, html: &str) -> Vec<Token> {
+        let mut results = Vec::new();
+
+        let state = &mut self.state;
+        let buffer = &mut self.buffer;
+        let escape = &mut self.escape;
+
+        for grapheme in UnicodeSegmentation::graphemes(html, true) {
+            if *state == ParseState::InTag {
+                if grapheme == ">" {
+                    results.push(Token::Tag(buffer.clone()));
                     buffer.clear();
-                    state = ParseState::Text;
+                    *state = ParseState::Text;
+                } else {
+                    buffer.push_str(grapheme);
                 }
-                _ if state == ParseState::EscapeSequence => {
-                    if grapheme.trim().is_empty() {
-                        results.push(Token::Word(format!("&{buffer}")));
-                        buffer.clear();
-                        state = ParseState::Text;
-                    } else {
-                        buffer.push_str(grapheme);
-                    }
-                }
-                _ => {
-                    if grapheme.trim().is_empty() {
+            } else {
+                match grapheme {
+                    "<" => {
+                        if *state == ParseState::EscapeSequence {
+                            buffer.push('&');
+                            buffer.push_str(escape);
+                        }
                         results.push(Token::Word(buffer.clone()));
                         buffer.clear();
-                    } else {
-                        buffer.push_str(grapheme);
+                        *state = ParseState::InTag;
+                    }
+                    // Character references are part of the word around them, so that a soft
+                    // hyphen can mark where a word may break
+                    "&" => {
+                        if *state == ParseState::EscapeSequence {
+                            buffer.push('&');
+                            buffer.push_str(escape);
+                        }
+                        escape.clear();
+                        *state = ParseState::EscapeSequence;
+                    }
+                    ";" if *state == ParseState::EscapeSequence => {
+                        match escape.as_str() {
+                            "lt" => buffer.push('<'),
+                            "gt" => buffer.push('>'),
+                            "amp" => buffer.push('&'),
+                            "quot" => buffer.push('"'),
+                            "shy" => buffer.push(SOFT_HYPHEN),
+                            "nbsp" => buffer.push('\u{A0}'),
+                            _ => {
+                                buffer.push('&');
+                                buffer.push_str(escape);
+                                buffer.push(';');
+                            }
+                        }
+                        *state = ParseState::Text;
+                    }
+                    _ if *state == ParseState::EscapeSequence => {
+                        if grapheme.trim().is_empty() {
+                            buffer.push('&');
+                            buffer.push_str(escape);
+                            results.push(Token::Word(buffer.clone()));
+                            buffer.clear();
+                            *state = ParseState::Text;
+                        } else {
+                            escape.push_str(grapheme);
+                        }
+                    }
+                    _ => {
+                        if grapheme.trim().is_empty() {
+                            results.push(Token::Word(buffer.clone()));
+                            buffer.clear();
+                        } else {
+                            buffer.push_str(grapheme);
+                        }
                     }
                 }
             }
         }
+
+        results
     }
 
-    results
+    /**
+     * Mark the end of the input, returning the word it ended partway through, if any. A tag that
+     * was never closed is dropped.
+     */
+    pub fn finish(&mut self) -> Vec<Token> {
+        let mut word = mem::take(&mut self.buffer);
+        match mem::replace(&mut self.state, ParseState::Text) {
+            ParseState::InTag => return Vec::new(),
+            ParseState::EscapeSequence => {
+                word.push('&');
+                word.push_str(&self.escape);
+            }
+            ParseState::Text => {}
+        }
+        if word.is_empty() {
+            Vec::new()
+        } else {
+            vec![Token::Word(word)]
+        }
+    }
+}
+
+/**
+ * A tag split into its name and attributes. Closing tags keep their leading slash in the name.
+ */
+pub struct Tag {
+    pub name: String,
+    pub attributes: HashMap<String, String>,
+}
+
+impl Tag {
+    pub fn parse(tag: &str) -> Tag {
+        let tag = tag.trim().trim_end_matches('/');
+        let (name, mut rest) = tag
+            .split_once(|c: char| c.is_whitespace())
+            .unwrap_or((tag, ""));
+
+        let mut attributes = HashMap::new();
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            let key_end = rest
+                .find(|c: char| c == '=' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            let key = rest[..key_end].to_ascii_lowercase();
+            rest = rest[key_end..].trim_start();
+
+            let value = if let Some(after_equals) = rest.strip_prefix('=') {
+                let after_equals = after_equals.trim_start();
+                match after_equals.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let inner = &after_equals[1..];
+                        let value_end = inner.find(quote).unwrap_or(inner.len());
+                        rest = inner.get(value_end + 1..).unwrap_or("");
+                        &inner[..value_end]
+                    }
+                    _ => {
+                        let value_end = after_equals
+                            .find(char::is_whitespace)
+                            .unwrap_or(after_equals.len());
+                        rest = &after_equals[value_end..];
+                        &after_equals[..value_end]
+                    }
+                }
+            } else {
+                ""
+            };
+            attributes.insert(key, value.to_string());
+        }
+
+        Tag {
+            name: name.to_ascii_lowercase(),
+            attributes,
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -72,3 +202,92 @@ enum ParseState {
     EscapeSequence,
     Text,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Lex each chunk in turn, writing tags in angle brackets and dropping the empty words left
+     * between runs of whitespace
+     */
+    fn lex(chunks: &[&str]) -> Vec<String> {
+        let mut lexer = Lexer::new();
+        chunks
+            .iter()
+            .flat_map(|chunk| lexer.feed(chunk))
+            .filter_map(|token| match token {
+                Token::Tag(tag) => Some(format!("<{tag}>")),
+                Token::Word(word) if word.is_empty() => None,
+                Token::Word(word) => Some(word),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn splits_words_and_tags() {
+        assert_eq!(
+            lex(&["<p class=x>Hello,  big\nworld</p>"]),
+            ["<p class=x>", "Hello,", "big", "world", "</p>"]
+        );
+    }
+
+    #[test]
+    fn decodes_character_references_inside_words() {
+        assert_eq!(
+            lex(&["a&lt;b&gt;c &amp; &quot;q&quot; x&nbsp;y hy&shy;phen</p>"]),
+            ["a<b>c", "&", "\"q\"", "x\u{A0}y", "hy\u{AD}phen", "</p>"]
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_and_unterminated_references() {
+        assert_eq!(
+            lex(&["&bogus; fish &chips <b>&amp</b>"]),
+            ["&bogus;", "fish", "&chips", "<b>", "&amp", "</b>"]
+        );
+    }
+
+    #[test]
+    fn carries_partial_tokens_between_chunks() {
+        assert_eq!(
+            lex(&["<di", "v>wo", "rd &a", "mp; end</", "div>"]),
+            ["<div>", "word", "&", "end", "</div>"]
+        );
+    }
+
+    #[test]
+    fn holds_a_trailing_word_until_it_ends() {
+        let mut lexer = Lexer::new();
+        assert!(lexer.feed("unfinished").is_empty());
+        assert!(matches!(&lexer.feed(" ")[..], [Token::Word(word)] if word == "unfinished"));
+    }
+
+    #[test]
+    fn finishing_flushes_the_trailing_word() {
+        let mut lexer = Lexer::new();
+        assert_eq!(lexer.feed("<p>last").len(), 2);
+        assert!(matches!(&lexer.finish()[..], [Token::Word(word)] if word == "last"));
+        assert!(lexer.finish().is_empty());
+
+        lexer.feed("fish &chips");
+        assert!(matches!(&lexer.finish()[..], [Token::Word(word)] if word == "&chips"));
+        lexer.feed("<unclosed");
+        assert!(lexer.finish().is_empty());
+    }
+
+    #[test]
+    fn keeps_combining_characters_with_their_base() {
+        assert_eq!(lex(&["cafe\u{301} <i>"]), ["cafe\u{301}", "<i>"]);
+    }
+
+    #[test]
+    fn parses_attributes() {
+        let tag = Tag::parse("A HREF=\"/x y\" title='it''s' hidden data-n=3 /");
+        assert_eq!(tag.name, "a");
+        assert_eq!(tag.attributes["href"], "/x y");
+        assert_eq!(tag.attributes["title"], "it");
+        assert_eq!(tag.attributes["hidden"], "");
+        assert_eq!(tag.attributes["data-n"], "3");
+    }
+}
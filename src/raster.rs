@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+
+use eframe::{
+    egui::{
+        epaint::{ClippedPrimitive, ImageDelta, Primitive, Vertex},
+        Color32, ColorImage, ImageData, Pos2, Rect, TextureId, TexturesDelta,
+    },
+    epaint::Mesh,
+};
+use image::RgbaImage;
+
+/**
+ * A premultiplied colour with channels from 0 to 1, in the same gamma space egui blends in
+ */
+type Rgba = [f32; 4];
+
+/**
+ * The textures egui has handed out, kept on the CPU so meshes can be drawn without a GPU
+ */
+#[derive(Default)]
+pub struct Textures {
+    images: HashMap<TextureId, ColorImage>,
+}
+
+impl Textures {
+    /**
+     * Apply the texture uploads from a frame. Frees are ignored: a freed texture is never drawn
+     * again, and the screenshot is taken before there's a chance to reuse its id.
+     */
+    pub fn update(&mut self, delta: &TexturesDelta) {
+        for (id, ImageDelta { image, pos, .. }) in delta.set.iter() {
+            let image = match image {
+                ImageData::Color(image) => (**image).clone(),
+                ImageData::Font(font) => ColorImage {
+                    size: font.size,
+                    pixels: font.srgba_pixels(None).collect(),
+                },
+            };
+            match (pos, self.images.get_mut(id)) {
+                (Some([x, y]), Some(existing)) => {
+                    for row in 0..image.height() {
+                        let start = (y + row) * existing.width() + x;
+                        let source = &image.pixels[row * image.width()..(row + 1) * image.width()];
+                        existing.pixels[start..start + image.width()].copy_from_slice(source);
+                    }
+                }
+                _ => {
+                    self.images.insert(*id, image);
+                }
+            }
+        }
+    }
+
+    /**
+     * Bilinearly sample a texture at normalised coordinates, clamping at the edges. Missing
+     * textures read as opaque white, like an untextured mesh.
+     */
+    fn sample(&self, id: TextureId, uv: Pos2) -> Rgba {
+        let Some(image) = self.images.get(&id) else {
+            return [1.; 4];
+        };
+        let [width, height] = image.size;
+        if width == 0 || height == 0 {
+            return [1.; 4];
+        }
+        let x = (uv.x * width as f32 - 0.5).clamp(0., (width - 1) as f32);
+        let y = (uv.y * height as f32 - 0.5).clamp(0., (height - 1) as f32);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let texel = |x: usize, y: usize| rgba(image.pixels[y * width + x]);
+        let top = mix(texel(x0, y0), texel(x1, y0), fx);
+        let bottom = mix(texel(x0, y1), texel(x1, y1), fx);
+        mix(top, bottom, fy)
+    }
+}
+
+/**
+ * Draw tessellated shapes into a `width` by `height` pixel image, one pixel per point. egui's
+ * tessellator already feathers edges for anti-aliasing, so each pixel is simply sampled at its
+ * centre.
+ */
+pub fn rasterize(
+    primitives: &[ClippedPrimitive],
+    textures: &Textures,
+    width: u32,
+    height: u32,
+) -> RgbaImage {
+    let mut pixels: Vec<Rgba> = vec![[0., 0., 0., 1.]; width as usize * height as usize];
+    let bounds = Rect::from_min_size(Pos2::ZERO, [width as f32, height as f32].into());
+    for ClippedPrimitive {
+        clip_rect,
+        primitive,
+    } in primitives
+    {
+        // Paint callbacks need a GPU, and the display list never makes any
+        let Primitive::Mesh(mesh) = primitive else {
+            continue;
+        };
+        let clip = clip_rect.intersect(bounds);
+        if clip.is_negative() {
+            continue;
+        }
+        draw_mesh(&mut pixels, width as usize, clip, mesh, textures);
+    }
+
+    let mut image = RgbaImage::new(width, height);
+    for (pixel, color) in image.pixels_mut().zip(pixels) {
+        // The background is opaque, so the premultiplied colour is the final one
+        pixel.0 = color.map(|channel| (channel.clamp(0., 1.) * 255.).round() as u8);
+    }
+    image
+}
+
+fn draw_mesh(pixels: &mut [Rgba], width: usize, clip: Rect, mesh: &Mesh, textures: &Textures) {
+    for triangle in mesh.indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| &mesh.vertices[triangle[i] as usize]);
+        draw_triangle(pixels, width, clip, [a, b, c], mesh.texture_id, textures);
+    }
+}
+
+/**
+ * Fill the pixels whose centres fall inside a triangle, whichever way round its corners are wound.
+ * A centre exactly on an edge belongs to only one of the two triangles sharing it, so translucent
+ * meshes aren't blended twice along their seams.
+ */
+fn draw_triangle(
+    pixels: &mut [Rgba],
+    width: usize,
+    clip: Rect,
+    [a, b, c]: [&Vertex; 3],
+    texture: TextureId,
+    textures: &Textures,
+) {
+    let area = edge(a.pos, b.pos, c.pos);
+    if area == 0. {
+        return;
+    }
+    // Wind every triangle the same way, so that each edge faces the inside the same way
+    let (b, c, area) = if area < 0. {
+        (c, b, -area)
+    } else {
+        (b, c, area)
+    };
+    let edges = [(b.pos, c.pos), (c.pos, a.pos), (a.pos, b.pos)];
+    let min_x = a.pos.x.min(b.pos.x).min(c.pos.x).max(clip.min.x).floor() as usize;
+    let min_y = a.pos.y.min(b.pos.y).min(c.pos.y).max(clip.min.y).floor() as usize;
+    let max_x = a.pos.x.max(b.pos.x).max(c.pos.x).min(clip.max.x).ceil() as usize;
+    let max_y = a.pos.y.max(b.pos.y).max(c.pos.y).min(clip.max.y).ceil() as usize;
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let centre = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+            if !clip.contains(centre) {
+                continue;
+            }
+            // Barycentric weights, which are all non-negative inside the triangle
+            let weights = edges.map(|(from, to)| edge(from, to, centre) / area);
+            let inside = weights
+                .iter()
+                .zip(edges)
+                .all(|(weight, (from, to))| *weight > 0. || *weight == 0. && owns_edge(from, to));
+            if !inside {
+                continue;
+            }
+            let uv = a.uv.to_vec2() * weights[0]
+                + b.uv.to_vec2() * weights[1]
+                + c.uv.to_vec2() * weights[2];
+            let shade = [rgba(a.color), rgba(b.color), rgba(c.color)];
+            let texel = textures.sample(texture, uv.to_pos2());
+            let source: Rgba = std::array::from_fn(|channel| {
+                let shade: f32 = (0..3).map(|i| shade[i][channel] * weights[i]).sum();
+                shade * texel[channel]
+            });
+            let destination = &mut pixels[y * width + x];
+            for channel in 0..4 {
+                destination[channel] = source[channel] + destination[channel] * (1. - source[3]);
+            }
+        }
+    }
+}
+
+/**
+ * Twice the signed area of the triangle (a, b, point)
+ */
+fn edge(a: Pos2, b: Pos2, point: Pos2) -> f32 {
+    (b.x - a.x) * (point.y - a.y) - (b.y - a.y) * (point.x - a.x)
+}
+
+/**
+ * Whether a triangle owns the pixel centres on one of its edges. The triangle on the other side
+ * runs along the same edge the opposite way, so exactly one of them does.
+ */
+fn owns_edge(from: Pos2, to: Pos2) -> bool {
+    let direction = to - from;
+    direction.y > 0. || direction.y == 0. && direction.x < 0.
+}
+
+fn rgba(color: Color32) -> Rgba {
+    color.to_array().map(|channel| channel as f32 / 255.)
+}
+
+fn mix(from: Rgba, to: Rgba, amount: f32) -> Rgba {
+    std::array::from_fn(|channel| from[channel] + (to[channel] - from[channel]) * amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use eframe::egui::{TextureOptions, Vec2};
+
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+    fn rect(min: (f32, f32), max: (f32, f32)) -> Rect {
+        Rect::from_min_max(Pos2::new(min.0, min.1), Pos2::new(max.0, max.1))
+    }
+
+    fn draw(meshes: Vec<(Rect, Mesh)>, textures: &Textures, width: u32, height: u32) -> RgbaImage {
+        let primitives: Vec<ClippedPrimitive> = meshes
+            .into_iter()
+            .map(|(clip_rect, mesh)| ClippedPrimitive {
+                clip_rect,
+                primitive: Primitive::Mesh(mesh),
+            })
+            .collect();
+        rasterize(&primitives, textures, width, height)
+    }
+
+    fn solid(rect: Rect, color: Color32) -> Mesh {
+        let mut mesh = Mesh::default();
+        mesh.add_colored_rect(rect, color);
+        mesh
+    }
+
+    fn rows(image: &RgbaImage) -> Vec<Vec<[u8; 4]>> {
+        (0..image.height())
+            .map(|y| {
+                (0..image.width())
+                    .map(|x| image.get_pixel(x, y).0)
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn set(
+        id: TextureId,
+        pos: Option<[usize; 2]>,
+        pixels: &[[u8; 4]],
+        width: usize,
+    ) -> TexturesDelta {
+        let image = ColorImage {
+            size: [width, pixels.len() / width],
+            pixels: pixels
+                .iter()
+                .map(|[r, g, b, a]| Color32::from_rgba_premultiplied(*r, *g, *b, *a))
+                .collect(),
+        };
+        let image = ImageData::Color(image.into());
+        let delta = match pos {
+            Some(pos) => ImageDelta::partial(pos, image, TextureOptions::NEAREST),
+            None => ImageDelta::full(image, TextureOptions::NEAREST),
+        };
+        TexturesDelta {
+            set: vec![(id, delta)],
+            free: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn fills_a_solid_quad() {
+        let screen = rect((0., 0.), (4., 3.));
+        let mesh = solid(rect((1., 1.), (3., 2.)), Color32::RED);
+        let image = draw(vec![(screen, mesh)], &Textures::default(), 4, 3);
+        assert_eq!(
+            rows(&image),
+            [
+                [BLACK, BLACK, BLACK, BLACK],
+                [BLACK, RED, RED, BLACK],
+                [BLACK, BLACK, BLACK, BLACK],
+            ]
+        );
+    }
+
+    #[test]
+    fn clips_to_the_image_and_the_clip_rect() {
+        // Hangs off every edge of the image
+        let everywhere = (
+            rect((-10., -10.), (10., 10.)),
+            solid(rect((-5., -5.), (8., 8.)), Color32::RED),
+        );
+        // Only the part inside its clip rect is drawn
+        let clipped = (
+            rect((2., 0.), (3., 2.)),
+            solid(rect((1., 1.), (9., 9.)), Color32::BLUE),
+        );
+        let image = draw(vec![everywhere, clipped], &Textures::default(), 3, 2);
+        assert_eq!(rows(&image), [[RED, RED, RED], [RED, RED, BLUE]]);
+
+        let offscreen = (
+            rect((0., 0.), (3., 2.)),
+            solid(rect((5., 5.), (9., 9.)), Color32::RED),
+        );
+        let image = draw(vec![offscreen], &Textures::default(), 3, 2);
+        assert_eq!(rows(&image), [[BLACK; 3], [BLACK; 3]]);
+    }
+
+    #[test]
+    fn blends_translucent_colors() {
+        let screen = rect((0., 0.), (1., 1.));
+        let half_white = Color32::from_rgba_premultiplied(128, 128, 128, 128);
+        let mesh = solid(screen, half_white);
+        let image = draw(vec![(screen, mesh)], &Textures::default(), 1, 1);
+        assert_eq!(rows(&image), [[[128, 128, 128, 255]]]);
+    }
+
+    #[test]
+    fn samples_textures_and_applies_partial_updates() {
+        let id = TextureId::User(1);
+        let mut textures = Textures::default();
+        textures.update(&set(id, None, &[RED, BLUE, RED, BLUE], 2));
+
+        let screen = rect((0., 0.), (4., 2.));
+        let quad = || {
+            let mut mesh = Mesh::with_texture(id);
+            mesh.add_rect_with_uv(
+                screen,
+                Rect::from_min_size(Pos2::ZERO, Vec2::splat(1.)),
+                Color32::WHITE,
+            );
+            mesh
+        };
+        let image = draw(vec![(screen, quad())], &textures, 4, 2);
+        // Each texel covers two pixels, which sit a quarter texel either side of its centre
+        let blend = [191, 0, 64, 255];
+        assert_eq!(rows(&image)[0], [RED, blend, [64, 0, 191, 255], BLUE]);
+
+        textures.update(&set(id, Some([1, 1]), &[GREEN], 1));
+        let image = draw(vec![(screen, quad())], &textures, 4, 2);
+        assert_eq!(rows(&image)[0], [RED, blend, [64, 0, 191, 255], BLUE]);
+        assert_eq!(rows(&image)[1][3], GREEN);
+    }
+}
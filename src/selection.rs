@@ -0,0 +1,161 @@
+use eframe::egui::{text::CCursor, Pos2, Rect};
+
+use shelves::layout::DisplayListItem;
+
+/**
+ * A point between two glyphs: the index of a display list item and a character offset inside it
+ */
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct TextPosition {
+    pub item: usize,
+    pub offset: usize,
+}
+
+/**
+ * A range of selected text. The anchor is where the selection started and the focus is where it
+ * currently ends, which may come before the anchor.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct Selection {
+    pub anchor: TextPosition,
+    pub focus: TextPosition,
+}
+
+impl Selection {
+    pub fn collapsed(position: TextPosition) -> Selection {
+        Selection {
+            anchor: position,
+            focus: position,
+        }
+    }
+
+    pub fn word(display_list: &[DisplayListItem], item: usize) -> Selection {
+        Selection {
+            anchor: TextPosition { item, offset: 0 },
+            focus: TextPosition {
+                item,
+                offset: char_count(&display_list[item]),
+            },
+        }
+    }
+
+    pub fn all(display_list: &[DisplayListItem]) -> Option<Selection> {
+        let last = display_list.len().checked_sub(1)?;
+        Some(Selection {
+            anchor: TextPosition { item: 0, offset: 0 },
+            focus: TextPosition {
+                item: last,
+                offset: char_count(&display_list[last]),
+            },
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.focus
+    }
+
+    fn ordered(&self) -> (TextPosition, TextPosition) {
+        if self.anchor <= self.focus {
+            (self.anchor, self.focus)
+        } else {
+            (self.focus, self.anchor)
+        }
+    }
+
+    /**
+     * The selected character range within each item the selection touches
+     */
+    fn spans<'a>(
+        &self,
+        display_list: &'a [DisplayListItem],
+    ) -> impl Iterator<Item = (&'a DisplayListItem, usize, usize)> {
+        let (start, end) = self.ordered();
+        display_list
+            .iter()
+            .enumerate()
+            .skip(start.item)
+            .take(end.item + 1 - start.item)
+            .map(move |(index, item)| {
+                let from = if index == start.item { start.offset } else { 0 };
+                let to = if index == end.item {
+                    end.offset
+                } else {
+                    char_count(item)
+                };
+                (item, from, to)
+            })
+            .filter(|(_, from, to)| from < to)
+    }
+
+    /**
+     * Rectangles in page coordinates covering the selected glyphs
+     */
+    pub fn highlight_rects(&self, display_list: &[DisplayListItem]) -> Vec<Rect> {
+        self.spans(display_list)
+            .filter_map(|(item, from, to)| {
+                let galley = item.galley()?;
+                // Right-to-left text is drawn reversed, so its start can be on the right
+                let x = |offset| {
+                    let cursor = CCursor::new(item.visual_offset(offset));
+                    galley.pos_from_ccursor(cursor).left()
+                };
+                let (left, right) = (x(from).min(x(to)), x(from).max(x(to)));
+                Some(Rect::from_x_y_ranges(
+                    item.pos.x + left..=item.pos.x + right,
+                    item.pos.y..=item.pos.y + galley.size().y,
+                ))
+            })
+            .collect()
+    }
+
+    /**
+     * The selected text, with words on the same line or wrapped onto the next joined by spaces and
+     * hard line breaks kept as newlines
+     */
+    pub fn text(&self, display_list: &[DisplayListItem]) -> String {
+        let mut text = String::new();
+        let mut previous_block = None;
+        for (item, from, to) in self.spans(display_list) {
+            match previous_block {
+                Some(block) if block != item.block => text.push('\n'),
+                Some(_) => text.push(' '),
+                None => {}
+            }
+            previous_block = Some(item.block);
+            text.extend(item.text().chars().skip(from).take(to - from));
+        }
+        text
+    }
+}
+
+/**
+ * Find the position in the text closest to a point on the page. Points between lines go to the
+ * nearest line, and points beside a line go to the nearest end of it.
+ */
+pub fn hit_test(display_list: &[DisplayListItem], pos: Pos2) -> Option<TextPosition> {
+    let (index, item) = display_list
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| distance(a.rect(), pos).total_cmp(&distance(b.rect(), pos)))?;
+    let offset = item.galley().map_or(0, |galley| {
+        item.logical_offset(galley.cursor_from_pos(pos - item.pos).ccursor.index)
+    });
+    Some(TextPosition {
+        item: index,
+        offset,
+    })
+}
+
+/**
+ * How far a point is from a rectangle, counting vertical distance as far more important so that
+ * the pointer sticks to the line it is on
+ */
+fn distance(rect: Rect, pos: Pos2) -> f32 {
+    let dx = (rect.left() - pos.x).max(pos.x - rect.right()).max(0.);
+    let dy = (rect.top() - pos.y).max(pos.y - rect.bottom()).max(0.);
+    dy * 1000. + dx
+}
+
+fn char_count(item: &DisplayListItem) -> usize {
+    item.text().chars().count()
+}
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use eframe::egui::{
+    epaint::text::{Glyph, Row},
+    text::LayoutJob,
+    Context, Galley, Pos2, Rect, Vec2,
+};
+
+/**
+ * Turns styled text into positioned glyphs. Layout only measures text through this, so it can run
+ * without a window.
+ */
+pub trait TextShaper {
+    /**
+     * Lay the text out on a single line. Layout does its own line breaking, so jobs never wrap.
+     */
+    fn shape(&self, job: LayoutJob) -> Arc<Galley>;
+}
+
+/**
+ * Shape with egui's fonts, as drawn on screen
+ */
+impl TextShaper for Context {
+    fn shape(&self, job: LayoutJob) -> Arc<Galley> {
+        self.fonts(|fonts| fonts.layout_job(job))
+    }
+}
+
+/**
+ * A monospace font where every character is half as wide as the font is tall and the baseline
+ * sits four fifths of the way down, so tests can work out positions by hand
+ */
+pub struct FixedWidth;
+
+impl FixedWidth {
+    pub const ADVANCE: f32 = 0.5;
+    pub const ASCENT: f32 = 0.8;
+}
+
+impl TextShaper for FixedWidth {
+    fn shape(&self, job: LayoutJob) -> Arc<Galley> {
+        let height = job
+            .sections
+            .iter()
+            .map(|section| section.format.font_id.size)
+            .fold(0., f32::max);
+        let baseline = height * FixedWidth::ASCENT;
+        let mut glyphs = Vec::new();
+        let mut x = 0.;
+        for (index, section) in job.sections.iter().enumerate() {
+            let size = section.format.font_id.size;
+            x += section.leading_space;
+            for chr in job.text[section.byte_range.clone()].chars() {
+                glyphs.push(Glyph {
+                    chr,
+                    pos: Pos2::new(x, baseline),
+                    ascent: size * FixedWidth::ASCENT,
+                    size: Vec2::new(size * FixedWidth::ADVANCE, size),
+                    uv_rect: Default::default(),
+                    section_index: index as u32,
+                });
+                x += size * FixedWidth::ADVANCE;
+            }
+        }
+        let rect = Rect::from_min_size(Pos2::ZERO, Vec2::new(x, height));
+        Arc::new(Galley {
+            job: Arc::new(job),
+            rows: vec![Row {
+                section_index_at_start: 0,
+                glyphs,
+                rect,
+                visuals: Default::default(),
+                ends_with_newline: false,
+            }],
+            elided: false,
+            rect,
+            mesh_bounds: rect,
+            num_vertices: 0,
+            num_indices: 0,
+            pixels_per_point: 1.,
+        })
+    }
+}
@@ -0,0 +1,19 @@
+use std::collections::HashMap;
+
+/**
+ * Parse the declarations in a `style` attribute, like `color: red; list-style-type: square`.
+ * Property names are lowercased and anything malformed is skipped.
+ */
+pub fn parse_declarations(style: &str) -> HashMap<String, String> {
+    style
+        .split(';')
+        .filter_map(|declaration| declaration.split_once(':'))
+        .map(|(property, value)| {
+            (
+                property.trim().to_ascii_lowercase(),
+                value.trim().to_string(),
+            )
+        })
+        .filter(|(property, value)| !property.is_empty() && !value.is_empty())
+        .collect()
+}
@@ -0,0 +1,495 @@
+use std::sync::Arc;
+
+use eframe::egui::{Context, Rect, Ui, Vec2};
+
+use shelves::{
+    client::Client,
+    images::Images,
+    layout::{document_height, element_bounds, DisplayListItem, ElementId, VSTEP},
+    loader::{LoadEvent, Loader},
+    url::Url,
+    Page,
+};
+
+use crate::{find::Find, history::History, selection::Selection};
+
+#[derive(Clone)]
+pub enum Navigation {
+    Load(Url),
+    Back,
+    Forward,
+    Reload,
+}
+
+/**
+ * Where a page that is loading will end up once the server responds
+ */
+enum LoadTarget {
+    NewEntry(Url),
+    /// The history entry at `index`, which becomes current once the page commits
+    ExistingEntry {
+        index: usize,
+        scroll: Vec2,
+    },
+}
+
+struct Loading {
+    loader: Loader,
+    url: Url,
+    target: LoadTarget,
+}
+
+/**
+ * Why a page couldn't be shown, which the tab displays in place of the page
+ */
+pub struct LoadFailure {
+    /// The address that failed, as typed or as the URL it was parsed into
+    pub address: String,
+    /// The error followed by what caused it, outermost first
+    pub causes: Vec<String>,
+    /// How to try again, if trying again could help
+    pub retry: Option<Navigation>,
+}
+
+impl LoadFailure {
+    pub fn new(address: String, err: &anyhow::Error, retry: Option<Navigation>) -> LoadFailure {
+        let mut causes: Vec<String> = Vec::new();
+        for cause in err.chain() {
+            let cause = cause.to_string();
+            // Error types often repeat their source's message in their own, like "io error: {0}"
+            if !causes.last().is_some_and(|last| last.ends_with(&cause)) {
+                causes.push(cause);
+            }
+        }
+        LoadFailure {
+            address,
+            causes,
+            retry,
+        }
+    }
+}
+
+struct LayoutCache {
+    width: f32,
+    zoom: f32,
+    display_list: Vec<DisplayListItem>,
+    height: f32,
+}
+
+/**
+ * How quickly smooth scrolling catches up to its target, as a fraction of the distance per second
+ */
+const SMOOTH_SCROLL_RATE: f32 = 15.;
+
+/**
+ * A single page in the browser window, along with everything needed to navigate it
+ */
+pub struct Tab {
+    pub address: String,
+    pub history: History,
+    pub page: Page,
+    /// The page's images, which load in after its content
+    pub images: Images,
+    /// Where the page is scrolled to, which smooth scrolling animates towards
+    pub scroll: Vec2,
+    pub focused: Option<ElementId>,
+    pub selection: Option<Selection>,
+    pub find: Option<Find>,
+    /// Shown instead of the page when the last navigation failed
+    pub failure: Option<LoadFailure>,
+    displayed_scroll: Vec2,
+    viewport_height: f32,
+    pending: Option<Navigation>,
+    loading: Option<Loading>,
+    layout_cache: Option<LayoutCache>,
+    /// Bumped every time the page is laid out again, so anything derived from the display list
+    /// knows to recompute
+    layout_generation: u64,
+}
+
+impl Tab {
+    pub fn new() -> Tab {
+        Tab {
+            address: String::new(),
+            history: History::default(),
+            page: Page::empty(),
+            images: Images::new(),
+            scroll: Vec2::ZERO,
+            focused: None,
+            selection: None,
+            find: None,
+            failure: None,
+            displayed_scroll: Vec2::ZERO,
+            viewport_height: 0.,
+            pending: None,
+            loading: None,
+            layout_cache: None,
+            layout_generation: 0,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        if self.failure.is_some() {
+            return "Problem loading page".to_string();
+        }
+        match (&self.page.title, self.history.current()) {
+            (Some(title), _) => title.clone(),
+            (None, Some(entry)) => entry.url.to_string(),
+            (None, None) => "New Tab".to_string(),
+        }
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.pending.is_some() || self.loading.is_some()
+    }
+
+    /**
+     * Queue a navigation to start on the next call to `poll`
+     */
+    pub fn start_navigation(&mut self, navigation: Navigation) {
+        self.pending = Some(navigation);
+    }
+
+    /**
+     * Replace the page with an error page
+     */
+    pub fn fail(&mut self, failure: LoadFailure) {
+        self.address = failure.address.clone();
+        self.failure = Some(failure);
+        self.pending = None;
+        self.loading = None;
+        self.clear_page(Vec2::ZERO);
+    }
+
+    /**
+     * Try the navigation that failed again
+     */
+    pub fn retry(&mut self) {
+        if let Some(retry) = self
+            .failure
+            .as_ref()
+            .and_then(|failure| failure.retry.clone())
+        {
+            self.start_navigation(retry);
+        }
+    }
+
+    /**
+     * Cancel the navigation in progress, keeping whatever has loaded so far
+     */
+    pub fn stop(&mut self) {
+        self.pending = None;
+        self.loading = None;
+    }
+
+    /**
+     * Start any queued navigation and apply whatever the loader has reported since the last frame
+     */
+    pub fn poll(&mut self, ctx: &Context, client: &Arc<Client>) {
+        if let Some(navigation) = self.pending.take() {
+            self.begin_navigation(ctx, client, navigation);
+        }
+
+        if self.images.poll(ctx) {
+            self.layout_cache = None;
+        }
+
+        let events = match &mut self.loading {
+            Some(loading) => loading.loader.poll(),
+            None => return,
+        };
+        for event in events {
+            self.handle_load_event(ctx, client, event);
+        }
+    }
+
+    /**
+     * Lay out the page at the width of the given UI, reusing the previous layout if neither the
+     * width nor the zoom has changed
+     */
+    pub fn update_layout(&mut self, ui: &Ui, zoom: f32) {
+        let width = ui.min_rect().width();
+        self.viewport_height = ui.max_rect().height();
+        let previous_zoom = self.layout_cache.as_ref().map(|cache| cache.zoom);
+        if self
+            .layout_cache
+            .as_ref()
+            .is_none_or(|cache| cache.width != width || cache.zoom != zoom)
+        {
+            let display_list = self.page.layout(ui.ctx(), width, zoom, &self.images);
+            self.layout_cache = Some(LayoutCache {
+                width,
+                zoom,
+                height: document_height(&display_list[..]),
+                display_list,
+            });
+            self.layout_generation += 1;
+        }
+        // Stay at roughly the same place in the document when it grows or shrinks
+        if let Some(previous_zoom) = previous_zoom.filter(|previous| *previous != zoom) {
+            self.scroll *= zoom / previous_zoom;
+            self.displayed_scroll = self.scroll;
+        }
+        if let (Some(find), Some(cache)) = (&mut self.find, &self.layout_cache) {
+            find.update(&cache.display_list[..], self.layout_generation);
+        }
+        self.clamp_scroll();
+    }
+
+    pub fn display_list(&self) -> &[DisplayListItem] {
+        self.layout_cache
+            .as_ref()
+            .map_or(&[], |cache| &cache.display_list[..])
+    }
+
+    pub fn document_height(&self) -> f32 {
+        self.layout_cache.as_ref().map_or(0., |cache| cache.height)
+    }
+
+    pub fn viewport_height(&self) -> f32 {
+        self.viewport_height
+    }
+
+    /**
+     * How far the page can scroll before the end of the document reaches the bottom of the window
+     */
+    pub fn max_scroll(&self) -> f32 {
+        (self.document_height() - self.viewport_height).max(0.)
+    }
+
+    /**
+     * Move down the page by the given number of pixels, or up if it's negative
+     */
+    pub fn scroll_down(&mut self, px: f32) {
+        self.scroll.y -= px;
+        self.clamp_scroll();
+    }
+
+    /**
+     * Move by a screenful, keeping a line of overlap so the reader doesn't lose their place
+     */
+    pub fn scroll_page(&mut self, pages: f32) {
+        self.scroll_down(pages * (self.viewport_height - VSTEP).max(VSTEP));
+    }
+
+    pub fn scroll_to(&mut self, y: f32) {
+        self.scroll.y = -y;
+        self.clamp_scroll();
+    }
+
+    /**
+     * The scroll offset to draw this frame. Returns true if smooth scrolling needs another frame
+     * to reach its target.
+     */
+    pub fn animate_scroll(&mut self, smooth: bool, dt: f32) -> (Vec2, bool) {
+        let remaining = self.scroll - self.displayed_scroll;
+        if !smooth || remaining.length() < 0.5 {
+            self.displayed_scroll = self.scroll;
+            return (self.displayed_scroll, false);
+        }
+        self.displayed_scroll += remaining * (1. - (-SMOOTH_SCROLL_RATE * dt).exp());
+        (self.displayed_scroll, true)
+    }
+
+    /**
+     * Move focus to the next focusable element in document order, or the previous one, wrapping
+     * around at either end
+     */
+    pub fn focus_next(&mut self, backwards: bool) {
+        let mut elements: Vec<ElementId> = self
+            .display_list()
+            .iter()
+            .filter_map(|item| item.element)
+            .collect();
+        elements.sort_unstable();
+        elements.dedup();
+
+        let next = match (self.focused, backwards) {
+            (None, false) => elements.first(),
+            (None, true) => elements.last(),
+            (Some(focused), false) => elements
+                .iter()
+                .find(|element| **element > focused)
+                .or(elements.first()),
+            (Some(focused), true) => elements
+                .iter()
+                .rev()
+                .find(|element| **element < focused)
+                .or(elements.last()),
+        };
+        self.focused = next.copied();
+        self.scroll_to_focused();
+    }
+
+    /**
+     * The link to follow if the focused element is activated
+     */
+    pub fn focused_link(&self) -> Option<&str> {
+        let focused = self.focused?;
+        self.display_list()
+            .iter()
+            .find(|item| item.element == Some(focused))
+            .and_then(|item| item.link.as_deref())
+    }
+
+    pub fn focus_ring(&self) -> Option<Rect> {
+        element_bounds(self.display_list(), self.focused?)
+    }
+
+    pub fn selected_text(&self) -> Option<String> {
+        self.selection
+            .filter(|selection| !selection.is_empty())
+            .map(|selection| selection.text(self.display_list()))
+    }
+
+    /**
+     * Jump to the next find-in-page match, or the previous one
+     */
+    pub fn find_step(&mut self, backwards: bool) {
+        let Some(find) = &mut self.find else {
+            return;
+        };
+        find.step(backwards);
+        let current = find.current().copied();
+        let bounds = current.and_then(|current| {
+            current
+                .highlight_rects(self.display_list())
+                .into_iter()
+                .reduce(|a, b| a.union(b))
+        });
+        if let Some(bounds) = bounds {
+            self.scroll_into_view(bounds);
+        }
+    }
+
+    fn scroll_to_focused(&mut self) {
+        if let Some(bounds) = self.focus_ring() {
+            self.scroll_into_view(bounds);
+        }
+    }
+
+    /**
+     * Scroll just far enough that the given part of the page is entirely on screen
+     */
+    fn scroll_into_view(&mut self, bounds: Rect) {
+        let top = -self.scroll.y;
+        if bounds.top() < top {
+            self.scroll_to(bounds.top());
+        } else if bounds.bottom() > top + self.viewport_height {
+            self.scroll_to(bounds.bottom() - self.viewport_height);
+        }
+    }
+
+    /**
+     * Keep the page from scrolling sideways or past either end. While the page is still loading
+     * the document may grow, so only the top is enforced.
+     */
+    fn clamp_scroll(&mut self) {
+        self.scroll.x = 0.;
+        self.scroll.y = self.scroll.y.min(0.);
+        if !self.is_loading() {
+            self.scroll.y = self.scroll.y.max(-self.max_scroll());
+        }
+    }
+
+    fn begin_navigation(&mut self, ctx: &Context, client: &Arc<Client>, navigation: Navigation) {
+        let offset = match navigation {
+            Navigation::Load(url) => {
+                self.load(ctx, client, url.clone(), LoadTarget::NewEntry(url));
+                return;
+            }
+            Navigation::Back => -1,
+            Navigation::Forward => 1,
+            Navigation::Reload => 0,
+        };
+        let Some((index, entry)) = self.history.peek(offset) else {
+            return;
+        };
+        // A reload stays where the page is now rather than where it was when it was left
+        let scroll = if offset == 0 {
+            self.scroll
+        } else {
+            entry.scroll
+        };
+        let url = entry.url.clone();
+        self.load(
+            ctx,
+            client,
+            url,
+            LoadTarget::ExistingEntry { index, scroll },
+        );
+    }
+
+    fn load(&mut self, ctx: &Context, client: &Arc<Client>, url: Url, target: LoadTarget) {
+        self.address = url.to_string();
+        // Replacing an in-flight load drops its loader, which cancels it
+        let ctx = ctx.clone();
+        self.loading = Some(Loading {
+            loader: Loader::start(client.clone(), url.clone(), move || ctx.request_repaint()),
+            url,
+            target,
+        });
+    }
+
+    fn handle_load_event(&mut self, ctx: &Context, client: &Arc<Client>, event: LoadEvent) {
+        match event {
+            LoadEvent::Committed => {
+                let Some(loading) = &mut self.loading else {
+                    return;
+                };
+                let scroll = match &loading.target {
+                    LoadTarget::NewEntry(url) => {
+                        self.history.push(url.clone(), self.scroll);
+                        Vec2::ZERO
+                    }
+                    LoadTarget::ExistingEntry { index, scroll } => {
+                        self.history.go_to(*index, self.scroll);
+                        *scroll
+                    }
+                };
+                // Further events for this load belong to the entry it's now on
+                loading.target = LoadTarget::ExistingEntry {
+                    index: self.history.peek(0).map_or(0, |(index, _)| index),
+                    scroll,
+                };
+                self.failure = None;
+                self.clear_page(scroll);
+            }
+            LoadEvent::Tokens(tokens) => {
+                if let Some(entry) = self.history.current() {
+                    self.images.request_all(ctx, client, &entry.url, &tokens);
+                }
+                self.page.format_tokens.extend(tokens);
+                self.layout_cache = None;
+            }
+            LoadEvent::Title(title) => self.page.title = Some(title),
+            LoadEvent::Finished => self.loading = None,
+            LoadEvent::Failed(err) => {
+                let Some(loading) = self.loading.take() else {
+                    return;
+                };
+                // A page already in the history is retried in place rather than added again
+                let retry = match loading.target {
+                    LoadTarget::NewEntry(url) => Navigation::Load(url),
+                    // The error page takes the entry's place, so it's the one reloaded
+                    LoadTarget::ExistingEntry { index, .. } => {
+                        self.history.go_to(index, self.scroll);
+                        Navigation::Reload
+                    }
+                };
+                let err = anyhow::Error::from(err);
+                self.fail(LoadFailure::new(loading.url.to_string(), &err, Some(retry)));
+            }
+        }
+    }
+
+    fn clear_page(&mut self, scroll: Vec2) {
+        self.page = Page::empty();
+        self.images = Images::new();
+        self.focused = None;
+        self.selection = None;
+        self.scroll = scroll;
+        self.displayed_scroll = scroll;
+        self.layout_cache = None;
+    }
+}
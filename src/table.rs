@@ -0,0 +1,226 @@
+/**
+ * The largest colspan and rowspan HTML allows. Bigger values are treated as these.
+ */
+pub const MAX_COLSPAN: usize = 1000;
+pub const MAX_ROWSPAN: usize = 65534;
+
+/**
+ * The most columns a table's grid can have, however many cells its rows hold. Cells that would
+ * start past the last column share it instead.
+ */
+pub const MAX_COLUMNS: usize = 1000;
+
+/**
+ * Where a cell sits in its table's grid
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct Span {
+    pub row: usize,
+    pub column: usize,
+    pub rows: usize,
+    pub columns: usize,
+}
+
+/**
+ * Place each row's cells, given as (colspan, rowspan), into a grid. Cells skip over slots already
+ * taken by a cell spanning down from an earlier row. A rowspan of zero reaches the last row, and
+ * colspans are cut short at `MAX_COLUMNS`. Returns the cells' spans and the number of columns.
+ */
+pub fn place_cells(rows: &[Vec<(usize, usize)>]) -> (Vec<Span>, usize) {
+    let mut occupied: Vec<Vec<bool>> = vec![Vec::new(); rows.len()];
+    let mut spans = Vec::new();
+    let mut column_count = 0;
+    for (row, cells) in rows.iter().enumerate() {
+        let mut column = 0;
+        for &(columns, rows_spanned) in cells.iter() {
+            while occupied[row].get(column).copied().unwrap_or(false) {
+                column += 1;
+            }
+            column = column.min(MAX_COLUMNS - 1);
+            let columns = columns.clamp(1, MAX_COLUMNS - column);
+            let remaining = rows.len() - row;
+            let rows_spanned = if rows_spanned == 0 {
+                remaining
+            } else {
+                rows_spanned.min(remaining)
+            };
+            for taken in occupied[row..row + rows_spanned].iter_mut() {
+                if taken.len() < column + columns {
+                    taken.resize(column + columns, false);
+                }
+                taken[column..column + columns].fill(true);
+            }
+            spans.push(Span {
+                row,
+                column,
+                rows: rows_spanned,
+                columns,
+            });
+            column += columns;
+            column_count = column_count.max(column);
+        }
+    }
+    (spans, column_count)
+}
+
+/**
+ * Pick column widths from each cell's minimum width (its widest unbreakable item) and maximum
+ * width (its content on one line). Columns get their maximum width if it fits, otherwise the room
+ * beyond their minimum widths is shared out in proportion to how much more each would like.
+ */
+pub fn column_widths(
+    column_count: usize,
+    cells: &[(Span, f32, f32)],
+    available: f32,
+    spacing: f32,
+) -> Vec<f32> {
+    let mut min = vec![0f32; column_count];
+    let mut max = vec![0f32; column_count];
+    let mut cells: Vec<_> = cells.iter().collect();
+    // Narrow cells first, so spanning cells only widen columns that are still too narrow
+    cells.sort_by_key(|(span, _, _)| span.columns);
+    for (span, cell_min, cell_max) in cells {
+        let range = span.column..span.column + span.columns;
+        grow(&mut min[range.clone()], *cell_min, spacing);
+        grow(&mut max[range], *cell_max, spacing);
+    }
+    for (max, min) in max.iter_mut().zip(min.iter()) {
+        *max = max.max(*min);
+    }
+
+    let available = available - spacing * (column_count + 1) as f32;
+    let min_total: f32 = min.iter().sum();
+    let max_total: f32 = max.iter().sum();
+    if max_total <= available {
+        max
+    } else if min_total >= available || max_total <= min_total {
+        min
+    } else {
+        let share = (available - min_total) / (max_total - min_total);
+        min.iter()
+            .zip(max.iter())
+            .map(|(min, max)| min + (max - min) * share)
+            .collect()
+    }
+}
+
+/**
+ * Pick row heights so every cell fits. A cell spanning several rows adds any height it's still
+ * missing to the last of them.
+ */
+pub fn row_heights(row_count: usize, cells: &[(Span, f32)], spacing: f32) -> Vec<f32> {
+    let mut heights = vec![0f32; row_count];
+    let mut cells: Vec<_> = cells.iter().collect();
+    cells.sort_by_key(|(span, _)| span.rows);
+    for (span, height) in cells {
+        let rows = &mut heights[span.row..span.row + span.rows];
+        let current = extent(rows, spacing);
+        if let Some(last) = rows.last_mut() {
+            *last += (height - current).max(0.);
+        }
+    }
+    heights
+}
+
+/**
+ * The size of several adjacent columns or rows, including the spacing between them
+ */
+pub fn extent(sizes: &[f32], spacing: f32) -> f32 {
+    sizes.iter().sum::<f32>() + spacing * sizes.len().saturating_sub(1) as f32
+}
+
+/**
+ * Widen columns evenly until together they're at least `size` wide
+ */
+fn grow(columns: &mut [f32], size: f32, spacing: f32) {
+    let missing = size - extent(columns, spacing);
+    if missing > 0. && !columns.is_empty() {
+        let each = missing / columns.len() as f32;
+        for column in columns.iter_mut() {
+            *column += each;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Each cell's (row, column, rows, columns), and the number of columns
+     */
+    fn place(rows: &[Vec<(usize, usize)>]) -> (Vec<(usize, usize, usize, usize)>, usize) {
+        let (spans, column_count) = place_cells(rows);
+        let spans = spans
+            .iter()
+            .map(|span| (span.row, span.column, span.rows, span.columns))
+            .collect();
+        (spans, column_count)
+    }
+
+    fn span(column: usize, columns: usize) -> Span {
+        Span {
+            row: 0,
+            column,
+            rows: 1,
+            columns,
+        }
+    }
+
+    #[test]
+    fn places_cells_around_spans_from_earlier_rows() {
+        let (spans, column_count) =
+            place(&[vec![(1, 2), (2, 1)], vec![(1, 1), (1, 1)], vec![(0, 0)]]);
+        assert_eq!(
+            spans,
+            [
+                (0, 0, 2, 1),
+                (0, 1, 1, 2),
+                (1, 1, 1, 1),
+                (1, 2, 1, 1),
+                (2, 0, 1, 1)
+            ]
+        );
+        assert_eq!(column_count, 3);
+    }
+
+    #[test]
+    fn zero_rowspans_reach_the_last_row() {
+        let (spans, _) = place(&[vec![(1, 0), (1, 1)], vec![(1, 1)], vec![(1, 1)]]);
+        assert_eq!(spans[0], (0, 0, 3, 1));
+        assert_eq!(spans[2], (1, 1, 1, 1));
+        assert_eq!(spans[3], (2, 1, 1, 1));
+    }
+
+    #[test]
+    fn caps_the_number_of_columns() {
+        let (spans, column_count) = place(&[vec![(MAX_COLSPAN, 1); 5], vec![(1, 1); 2000]]);
+        assert_eq!(column_count, MAX_COLUMNS);
+        assert_eq!(spans[0], (0, 0, 1, MAX_COLUMNS));
+        assert!(spans
+            .iter()
+            .all(|(_, column, _, columns)| column + columns <= MAX_COLUMNS));
+        assert_eq!(spans.last(), Some(&(1, MAX_COLUMNS - 1, 1, 1)));
+    }
+
+    #[test]
+    fn columns_get_their_widest_content_when_it_fits() {
+        let cells = [(span(0, 1), 10., 40.), (span(1, 1), 20., 30.)];
+        assert_eq!(column_widths(2, &cells, 1000., 2.), [40., 30.]);
+    }
+
+    #[test]
+    fn columns_shrink_towards_their_narrowest_content() {
+        let cells = [(span(0, 1), 10., 40.), (span(1, 1), 20., 30.)];
+        // 6 for spacing leaves 44, which is 14 over the minimums of the 40 the columns would like
+        assert_eq!(column_widths(2, &cells, 50., 2.), [20.5, 23.5]);
+        assert_eq!(column_widths(2, &cells, 10., 2.), [10., 20.]);
+    }
+
+    #[test]
+    fn spanning_cells_share_the_width_they_still_need_evenly() {
+        // Two columns 10 and 0 wide, plus the spacing between them, are 20 short of 32
+        let cells = [(span(0, 1), 10., 10.), (span(0, 2), 32., 32.)];
+        assert_eq!(column_widths(2, &cells, 1000., 2.), [20., 10.]);
+    }
+}
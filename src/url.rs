@@ -1,4 +1,4 @@
-use std::{fmt::Display, num::ParseIntError, ops::Range};
+use std::{fmt::Display, num::ParseIntError, ops::Range, path::Path};
 
 use thiserror::Error;
 
@@ -12,9 +12,21 @@ pub struct Url {
 }
 
 const SCHEME_SEPERATOR: &str = "://";
+const DATA_SCHEME: &str = "data:";
 
 impl Url {
     pub fn new(url: String) -> Result<Url, UrlError> {
+        // data: URLs carry their content in place of a host and path
+        if let Some(data) = url.strip_prefix(DATA_SCHEME) {
+            return Ok(Url {
+                path: data.to_string(),
+                url,
+                scheme: Scheme::Data,
+                host: 0..0,
+                port: None,
+            });
+        }
+
         let scheme_end = url
             .find(SCHEME_SEPERATOR)
             .ok_or(UrlError::NoSchemeProvided)?;
@@ -57,6 +69,72 @@ impl Url {
         })
     }
 
+    /**
+     * Interpret text typed into the address bar, which may leave off the scheme or be a local
+     * file path
+     */
+    pub fn from_user_input(input: &str) -> Result<Url, UrlError> {
+        let input = input.trim();
+        if input.contains(SCHEME_SEPERATOR) || input.starts_with(DATA_SCHEME) {
+            return Url::new(input.to_string());
+        }
+
+        let path = Path::new(input);
+        if path.is_absolute() || path.exists() {
+            let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            return Url::new(format!("file://{}", path.display()));
+        }
+
+        Url::new(format!("https://{input}"))
+    }
+
+    /**
+     * Resolve a link found on this page, which may be absolute, scheme-relative, host-relative,
+     * just a query, or relative to this page's directory. Links with a scheme that can't be
+     * loaded, like `mailto:`, are an `UnknownScheme` error.
+     */
+    pub fn resolve(&self, href: &str) -> Result<Url, UrlError> {
+        let href = href.trim();
+        if href.starts_with(DATA_SCHEME) {
+            return Url::new(href.to_string());
+        }
+        let href = href.split_once('#').map_or(href, |(href, _fragment)| href);
+        // Without the query, which may contain slashes of its own
+        let path = self
+            .path
+            .split_once('?')
+            .map_or(&self.path[..], |(path, _query)| path);
+        if let Some(scheme) = scheme_of(href) {
+            match scheme {
+                "http" | "https" | "file" => Url::new(href.to_string()),
+                other => Err(UrlError::UnknownScheme(other.to_string())),
+            }
+        } else if let Some(rest) = href.strip_prefix("//") {
+            Url::new(format!("{}{rest}", self.scheme))
+        } else if href.starts_with('/') {
+            Ok(self.with_path(href.to_string()))
+        } else if href.starts_with('?') {
+            Ok(self.with_path(format!("{path}{href}")))
+        } else if href.is_empty() {
+            Ok(self.clone())
+        } else {
+            let (directory, _file) = path.rsplit_once('/').unwrap_or(("", ""));
+            let mut segments: Vec<&str> = directory.split('/').collect();
+            for segment in href.split('/') {
+                match segment {
+                    "." => {}
+                    ".." => {
+                        if segments.len() > 1 {
+                            segments.pop();
+                        }
+                    }
+                    segment => segments.push(segment),
+                }
+            }
+            Ok(self.with_path(segments.join("/")))
+        }
+    }
+
     pub fn with_path(&self, path: String) -> Url {
         Url {
             path,
@@ -64,6 +142,16 @@ impl Url {
         }
     }
 
+    /**
+     * The scheme, host and port, which identify the site this URL belongs to
+     */
+    pub fn origin(&self) -> String {
+        match self.port {
+            Some(port) => format!("{}{}:{port}", self.scheme, self.host()),
+            None => format!("{}{}", self.scheme, self.host()),
+        }
+    }
+
     pub fn host(&self) -> &str {
         &self.url[self.host.clone()]
     }
@@ -81,6 +169,18 @@ impl Url {
     }
 }
 
+/**
+ * The scheme a link starts with, like `mailto` in `mailto:someone@example.org`. A colon after a
+ * slash or a query, as in `search?q=a:b`, doesn't start a scheme.
+ */
+fn scheme_of(href: &str) -> Option<&str> {
+    let (scheme, _rest) = href.split_once(':')?;
+    let mut chars = scheme.chars();
+    let valid = chars.next()?.is_ascii_alphabetic()
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then_some(scheme)
+}
+
 impl Display for Url {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.scheme.fmt(f)?;
@@ -97,6 +197,7 @@ pub enum Scheme {
     Http,
     Https,
     File,
+    Data,
 }
 
 impl Display for Scheme {
@@ -105,6 +206,7 @@ impl Display for Scheme {
             Scheme::Http => write!(f, "http://"),
             Scheme::Https => write!(f, "https://"),
             Scheme::File => write!(f, "file://"),
+            Scheme::Data => write!(f, "{DATA_SCHEME}"),
         }
     }
 }
@@ -118,3 +220,124 @@ pub enum UrlError {
     #[error("unknown scheme: {0}")]
     UnknownScheme(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(text: &str) -> Url {
+        Url::new(text.to_string()).unwrap()
+    }
+
+    #[test]
+    fn splits_scheme_host_and_path() {
+        let url = url("https://example.org/a/b.html?q=1");
+        assert!(url.scheme() == Scheme::Https);
+        assert_eq!(url.host(), "example.org");
+        assert_eq!(url.port(), None);
+        assert_eq!(url.path(), "/a/b.html?q=1");
+    }
+
+    #[test]
+    fn missing_path_is_the_root() {
+        assert_eq!(url("http://example.org").path(), "/");
+    }
+
+    #[test]
+    fn parses_ports() {
+        let url = url("http://localhost:8080/index.html");
+        assert_eq!(url.host(), "localhost");
+        assert_eq!(url.port(), Some(8080));
+        assert_eq!(url.path(), "/index.html");
+        assert_eq!(url.to_string(), "http://localhost:8080/index.html");
+    }
+
+    #[test]
+    fn rejects_bad_ports() {
+        for bad in ["http://host:/", "http://host:http/", "http://host:65536/"] {
+            assert!(
+                matches!(Url::new(bad.to_string()), Err(UrlError::InvalidPortInt(_))),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn requires_a_known_scheme() {
+        assert!(matches!(
+            Url::new("example.org".to_string()),
+            Err(UrlError::NoSchemeProvided)
+        ));
+        assert!(matches!(
+            Url::new("gopher://example.org".to_string()),
+            Err(UrlError::UnknownScheme(scheme)) if scheme == "gopher"
+        ));
+    }
+
+    #[test]
+    fn file_urls_have_an_empty_host() {
+        let url = url("file:///tmp/page.html");
+        assert!(url.scheme() == Scheme::File);
+        assert_eq!(url.host(), "");
+        assert_eq!(url.path(), "/tmp/page.html");
+    }
+
+    #[test]
+    fn data_urls_keep_their_content_as_the_path() {
+        let url = url("data:text/html,<p>a://b</p>");
+        assert!(url.scheme() == Scheme::Data);
+        assert_eq!(url.path(), "text/html,<p>a://b</p>");
+        assert_eq!(url.to_string(), "data:text/html,<p>a://b</p>");
+    }
+
+    #[test]
+    fn resolves_relative_links() {
+        let base = url("http://example.org/docs/guide/intro.html");
+        let resolve = |href| base.resolve(href).unwrap().to_string();
+        assert_eq!(
+            resolve("next.html"),
+            "http://example.org/docs/guide/next.html"
+        );
+        assert_eq!(
+            resolve("../index.html"),
+            "http://example.org/docs/index.html"
+        );
+        assert_eq!(resolve("../../../up.html"), "http://example.org/up.html");
+        assert_eq!(resolve("/top.html#part"), "http://example.org/top.html");
+        assert_eq!(
+            resolve("//cdn.example.org/x.png"),
+            "http://cdn.example.org/x.png"
+        );
+        assert_eq!(resolve("https://other.org"), "https://other.org/");
+        assert_eq!(
+            resolve("search?to=http://x/y"),
+            "http://example.org/docs/guide/search?to=http://x/y"
+        );
+    }
+
+    #[test]
+    fn resolves_query_only_links_against_the_current_file() {
+        let base = url("http://example.org/docs/list.html?page=1&from=/a/b");
+        let resolve = |href| base.resolve(href).unwrap().to_string();
+        assert_eq!(
+            resolve("?page=2"),
+            "http://example.org/docs/list.html?page=2"
+        );
+        assert_eq!(resolve("item.html"), "http://example.org/docs/item.html");
+    }
+
+    #[test]
+    fn rejects_links_with_schemes_that_cant_be_loaded() {
+        let base = url("http://example.org/docs/intro.html");
+        for href in [
+            "mailto:someone@example.org",
+            "javascript:void(0)",
+            "tel:+15555550100",
+        ] {
+            assert!(
+                matches!(base.resolve(href), Err(UrlError::UnknownScheme(_))),
+                "{href}"
+            );
+        }
+    }
+}
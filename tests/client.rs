@@ -0,0 +1,389 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use shelves::{
+    client::{Body, CancelHandle, Client, RequestError, Timeouts},
+    layout::FormatToken,
+    url::Url,
+    Page,
+};
+
+/**
+ * An HTTP server on a local port which answers every request with whatever the handler returns
+ * for its path, keeping connections open until the client closes them
+ */
+struct TestServer {
+    port: u16,
+    connections: Arc<AtomicUsize>,
+}
+
+impl TestServer {
+    fn start(handler: impl Fn(&str) -> String + Send + Sync + 'static) -> TestServer {
+        let handler = Arc::new(handler);
+        TestServer::start_raw(move |stream| serve(stream, &*handler))
+    }
+
+    /**
+     * A server which handles each connection itself, for responses that take their time
+     */
+    fn start_raw(handler: impl Fn(TcpStream) + Send + Sync + 'static) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let handler = Arc::new(handler);
+        let counter = connections.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    return;
+                };
+                counter.fetch_add(1, Ordering::SeqCst);
+                let handler = handler.clone();
+                thread::spawn(move || handler(stream));
+            }
+        });
+        TestServer { port, connections }
+    }
+
+    fn url(&self, path: &str) -> Url {
+        Url::new(format!("http://127.0.0.1:{}{path}", self.port)).unwrap()
+    }
+
+    fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+fn serve(stream: TcpStream, handler: &dyn Fn(&str) -> String) {
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+            return;
+        }
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).unwrap_or(0) == 0 || header.trim().is_empty() {
+                break;
+            }
+        }
+        let path = request_line.split(' ').nth(1).unwrap_or("/");
+        if writer.write_all(handler(path).as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+/**
+ * Read a request's head, so the server can answer it
+ */
+fn read_request(stream: &TcpStream) {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+        line.clear();
+    }
+}
+
+fn ok(body: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+fn body_text(client: &Client, url: &Url) -> String {
+    let response = client.request(url).unwrap();
+    response.body.as_str().unwrap().to_string()
+}
+
+#[test]
+fn fetches_a_page() {
+    let server = TestServer::start(|path| ok(&format!("you asked for {path}")));
+    let response = Client::new().request(&server.url("/hello")).unwrap();
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.body.as_str().unwrap(), "you asked for /hello");
+}
+
+#[test]
+fn reads_chunked_responses() {
+    let server = TestServer::start(|_| {
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+         4\r\nWiki\r\n6\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\n\r\n"
+            .to_string()
+    });
+    let client = Client::new();
+    assert_eq!(
+        body_text(&client, &server.url("/")),
+        "Wikipedia in \r\n\r\nchunks."
+    );
+}
+
+#[test]
+fn follows_redirects() {
+    let server = TestServer::start(|path| match path {
+        "/old" => "HTTP/1.1 301 Moved Permanently\r\nLocation: /new\r\nContent-Length: 0\r\n\r\n"
+            .to_string(),
+        "/new" => ok("moved here"),
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string(),
+    });
+    let client = Client::new();
+    assert_eq!(body_text(&client, &server.url("/old")), "moved here");
+}
+
+#[test]
+fn follows_absolute_redirects() {
+    let server = TestServer::start(ok);
+    let target = server.url("/target");
+    let redirector = TestServer::start(move |_| {
+        format!("HTTP/1.1 302 Found\r\nLocation: {target}\r\nContent-Length: 0\r\n\r\n")
+    });
+    let client = Client::new();
+    assert_eq!(body_text(&client, &redirector.url("/")), "/target");
+}
+
+#[test]
+fn gives_up_on_redirect_loops() {
+    let server = TestServer::start(|_| {
+        "HTTP/1.1 302 Found\r\nLocation: /loop\r\nContent-Length: 0\r\n\r\n".to_string()
+    });
+    assert!(matches!(
+        Client::new().request(&server.url("/loop")),
+        Err(RequestError::MaximumRedirects)
+    ));
+}
+
+#[test]
+fn redirects_need_a_location() {
+    let server =
+        TestServer::start(|_| "HTTP/1.1 302 Found\r\nContent-Length: 0\r\n\r\n".to_string());
+    assert!(matches!(
+        Client::new().request(&server.url("/")),
+        Err(RequestError::NoRedirectFound)
+    ));
+}
+
+#[test]
+fn reuses_connections() {
+    let server = TestServer::start(ok);
+    let client = Client::new();
+    for path in ["/one", "/two", "/three"] {
+        assert_eq!(body_text(&client, &server.url(path)), path);
+    }
+    assert_eq!(server.connections(), 1);
+}
+
+#[test]
+fn slow_requests_dont_hold_up_others() {
+    // The slow server answers once the fast request is done, or gives up waiting long after
+    let (release, released) = mpsc::channel::<()>();
+    let released = Mutex::new(released);
+    let slow = TestServer::start(move |path| {
+        let _ = released
+            .lock()
+            .unwrap()
+            .recv_timeout(Duration::from_secs(30));
+        ok(path)
+    });
+    let fast = TestServer::start(ok);
+    let client = Arc::new(Client::new());
+    let waiting = client.clone();
+    let slow_url = slow.url("/slow");
+    let slow_request = thread::spawn(move || body_text(&waiting, &slow_url));
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(body_text(&client, &fast.url("/fast")), "/fast");
+    assert!(!slow_request.is_finished());
+    release.send(()).unwrap();
+    assert_eq!(slow_request.join().unwrap(), "/slow");
+}
+
+#[test]
+fn rejects_malformed_status_lines() {
+    let server = TestServer::start(|_| "garbage\r\n\r\n".to_string());
+    assert!(matches!(
+        Client::new().request(&server.url("/")),
+        Err(RequestError::BadHTTP)
+    ));
+}
+
+#[test]
+fn rejects_invalid_status_codes() {
+    let server =
+        TestServer::start(|_| "HTTP/1.1 two-hundred OK\r\nContent-Length: 0\r\n\r\n".to_string());
+    assert!(matches!(
+        Client::new().request(&server.url("/")),
+        Err(RequestError::InvalidStatusCode(code)) if code == "two-hundred"
+    ));
+}
+
+#[test]
+fn rejects_malformed_headers() {
+    let server = TestServer::start(|_| "HTTP/1.1 200 OK\r\nno colon here\r\n\r\n".to_string());
+    assert!(matches!(
+        Client::new().request(&server.url("/")),
+        Err(RequestError::BadHTTP)
+    ));
+}
+
+#[test]
+fn rejects_bodies_without_a_length() {
+    let server = TestServer::start(|_| "HTTP/1.1 200 OK\r\n\r\nhello".to_string());
+    assert!(matches!(
+        Client::new().request(&server.url("/")),
+        Err(RequestError::MissingContentLength)
+    ));
+}
+
+fn impatient_client() -> Client {
+    Client::with_timeouts(Timeouts {
+        connect: Some(Duration::from_secs(5)),
+        read: Some(Duration::from_millis(200)),
+        total: Some(Duration::from_secs(5)),
+    })
+}
+
+#[test]
+fn times_out_waiting_for_a_response() {
+    let server = TestServer::start(|path| {
+        thread::sleep(Duration::from_secs(2));
+        ok(path)
+    });
+    assert!(matches!(
+        impatient_client().request(&server.url("/")),
+        Err(RequestError::Timeout)
+    ));
+}
+
+#[test]
+fn times_out_on_slow_responses() {
+    let server = TestServer::start_raw(|mut stream| {
+        read_request(&stream);
+        let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n");
+        // Each byte arrives well within the read timeout, but the whole body takes too long
+        for _ in 0..100 {
+            thread::sleep(Duration::from_millis(20));
+            if stream.write_all(b"x").is_err() {
+                return;
+            }
+        }
+    });
+    let client = Client::with_timeouts(Timeouts {
+        connect: None,
+        read: Some(Duration::from_secs(1)),
+        total: Some(Duration::from_millis(300)),
+    });
+    assert!(matches!(
+        client.request(&server.url("/")),
+        Err(RequestError::Timeout)
+    ));
+}
+
+#[test]
+fn reconnects_after_a_timeout() {
+    let requests = AtomicUsize::new(0);
+    let server = TestServer::start(move |path| {
+        if requests.fetch_add(1, Ordering::SeqCst) == 0 {
+            thread::sleep(Duration::from_millis(500));
+        }
+        ok(path)
+    });
+    let client = impatient_client();
+    assert!(matches!(
+        client.request(&server.url("/slow")),
+        Err(RequestError::Timeout)
+    ));
+    // The late answer to the first request mustn't be taken for the second one's
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(body_text(&client, &server.url("/fast")), "/fast");
+    assert_eq!(server.connections(), 2);
+}
+
+#[test]
+fn cancels_requests() {
+    let server = TestServer::start(|path| {
+        thread::sleep(Duration::from_secs(5));
+        ok(path)
+    });
+    let cancel = CancelHandle::default();
+    let canceller = cancel.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        canceller.cancel();
+    });
+    assert!(matches!(
+        Client::new().request_cancellable(&server.url("/"), &cancel),
+        Err(RequestError::Cancelled)
+    ));
+}
+
+#[test]
+fn refuses_bodies_over_the_limit() {
+    let server = TestServer::start(|path| ok(&path.repeat(100)));
+    let mut client = Client::new();
+    client.set_max_body_size(100);
+    assert_eq!(body_text(&client, &server.url("/")), "/".repeat(100));
+    assert!(matches!(
+        client.request(&server.url("/big")),
+        Err(RequestError::BodyTooLarge(100))
+    ));
+}
+
+#[test]
+fn streams_bodies() {
+    let server = TestServer::start_raw(|mut stream| {
+        read_request(&stream);
+        let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n");
+        for piece in ["first ", "second ", "third"] {
+            thread::sleep(Duration::from_millis(20));
+            let chunk = format!("{:x}\r\n{piece}\r\n", piece.len());
+            if stream.write_all(chunk.as_bytes()).is_err() {
+                return;
+            }
+        }
+        let _ = stream.write_all(b"0\r\n\r\n");
+    });
+    let mut pieces = Vec::new();
+    let response = Client::new()
+        .request_streaming(&server.url("/"), &CancelHandle::default(), &mut |chunk| {
+            pieces.push(String::from_utf8(chunk.to_vec()).unwrap());
+            Ok(())
+        })
+        .unwrap();
+    assert!(matches!(response.body, Body::Streamed(18)));
+    assert_eq!(pieces, ["first ", "second ", "third"]);
+}
+
+#[test]
+fn stops_streaming_when_the_callback_fails() {
+    let server = TestServer::start(|_| ok("unwanted"));
+    let result =
+        Client::new().request_streaming(&server.url("/"), &CancelHandle::default(), &mut |_| {
+            Err(std::io::ErrorKind::Other.into())
+        });
+    assert!(matches!(result, Err(RequestError::IOError(_))));
+}
+
+#[test]
+fn loads_pages_through_the_loader() {
+    let server = TestServer::start(|_| ok("<title>Shelves</title><p>hello"));
+    let page = Page::load_with(&Arc::new(Client::new()), &server.url("/")).unwrap();
+    assert_eq!(page.title.as_deref(), Some("Shelves"));
+    let words: Vec<&str> = page
+        .format_tokens
+        .iter()
+        .filter_map(|token| match token {
+            FormatToken::Text { layout, .. } => Some(&layout.text[..]),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(words, ["hello"]);
+}